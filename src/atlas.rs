@@ -0,0 +1,479 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    sprite::Rect,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    components::{InterpolationBuffer, UserId},
+    events::GameEvent,
+};
+
+/// How long a player has to stand still before they stop walking, in seconds.
+/// Positions only change when updates arrive, so a player can look still for
+/// a frame or two while moving.
+const WALK_GRACE: f32 = 0.15;
+/// How often the server sends updates, in seconds.
+const SERVER_UPDATE_INTERVAL: f32 = 0.05;
+/// The speed the walk animation's `fps` is meant for, in pixels per second.
+const WALK_CLIP_SPEED: f32 = 100.;
+/// Slower than this counts as standing still, in pixels per second.
+const MIN_WALK_SPEED: f32 = 5.;
+/// Limits on how much faster or slower than its `fps` a walk plays.
+const MIN_WALK_RATE: f32 = 0.25;
+const MAX_WALK_RATE: f32 = 3.;
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct FrameRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct AnimationClip {
+    /// Indices into the descriptor's frames
+    pub frames: Vec<usize>,
+    #[serde(default = "default_fps")]
+    pub fps: f32,
+    #[serde(default = "default_looping")]
+    pub looping: bool,
+}
+
+fn default_fps() -> f32 {
+    10.
+}
+
+fn default_looping() -> bool {
+    true
+}
+
+/// The contents of an `.atlas.json` file: which parts of an image are frames,
+/// and which frames make up each animation.
+#[derive(Deserialize, Debug)]
+pub struct AtlasDescriptor {
+    /// Image holding the frames, relative to the descriptor
+    pub image: String,
+    /// Size of the image. Taken to be just big enough for the frames if not given
+    #[serde(default)]
+    pub size: Option<[f32; 2]>,
+    pub frames: Vec<FrameRect>,
+    #[serde(default)]
+    pub animations: HashMap<String, AnimationClip>,
+}
+
+impl AtlasDescriptor {
+    fn image_size(&self) -> Vec2 {
+        match self.size {
+            Some([width, height]) => Vec2::new(width, height),
+            None => self.frames.iter().fold(Vec2::ZERO, |size, frame| {
+                size.max(Vec2::new(frame.x + frame.width, frame.y + frame.height))
+            }),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.frames.is_empty() {
+            return Err("it has no frames".to_string());
+        }
+        let image_size = self.image_size();
+        for (index, frame) in self.frames.iter().enumerate() {
+            if frame.width <= 0. || frame.height <= 0. {
+                return Err(format!("frame {} is empty", index));
+            }
+            if frame.x < 0.
+                || frame.y < 0.
+                || frame.x + frame.width > image_size.x
+                || frame.y + frame.height > image_size.y
+            {
+                return Err(format!(
+                    "frame {} doesn't fit in the {}x{} image",
+                    index, image_size.x, image_size.y
+                ));
+            }
+        }
+        for (name, clip) in &self.animations {
+            if clip.frames.is_empty() {
+                return Err(format!("animation \"{}\" has no frames", name));
+            }
+            if let Some(frame) = clip
+                .frames
+                .iter()
+                .find(|frame| **frame >= self.frames.len())
+            {
+                return Err(format!(
+                    "animation \"{}\" uses frame {}, but there are only {}",
+                    name,
+                    frame,
+                    self.frames.len()
+                ));
+            }
+            if clip.fps <= 0. {
+                return Err(format!("animation \"{}\" has no speed", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A loaded atlas descriptor.
+#[derive(TypeUuid, Debug)]
+#[uuid = "8f1c3e0a-5d2b-4c7e-9a41-6b0d2f7e3c55"]
+pub struct SpriteSheet {
+    pub atlas: Handle<TextureAtlas>,
+    pub animations: HashMap<String, AnimationClip>,
+}
+
+impl SpriteSheet {
+    /// The clip called `name`, or the idle one if the sheet doesn't have it.
+    pub fn clip(&self, name: &str) -> Option<&AnimationClip> {
+        self.animations
+            .get(name)
+            .or_else(|| self.animations.get(PlayerPose::Idle.name()))
+    }
+}
+
+#[derive(Default)]
+pub struct SpriteSheetLoader;
+
+impl AssetLoader for SpriteSheetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let descriptor = serde_json::from_slice::<AtlasDescriptor>(bytes)?;
+            descriptor.validate().map_err(|e| {
+                bevy::asset::Error::msg(format!(
+                    "Invalid atlas {}: {}",
+                    load_context.path().display(),
+                    e
+                ))
+            })?;
+
+            let image_path = AssetPath::new(
+                load_context
+                    .path()
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(&descriptor.image),
+                None,
+            );
+            let mut atlas = TextureAtlas::new_empty(
+                load_context.get_handle(image_path.clone()),
+                descriptor.image_size(),
+            );
+            for frame in &descriptor.frames {
+                let min = Vec2::new(frame.x, frame.y);
+                atlas.add_texture(Rect {
+                    min,
+                    max: min + Vec2::new(frame.width, frame.height),
+                });
+            }
+            let atlas = load_context
+                .set_labeled_asset("atlas", LoadedAsset::new(atlas).with_dependency(image_path));
+
+            load_context.set_default_asset(LoadedAsset::new(SpriteSheet {
+                atlas,
+                animations: descriptor.animations,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["atlas.json"]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerPose {
+    Idle,
+    Walk,
+    Fire,
+}
+
+impl PlayerPose {
+    /// What the pose's animation is called in atlas descriptors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlayerPose::Idle => "idle",
+            PlayerPose::Walk => "walk",
+            PlayerPose::Fire => "fire",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PlayerAnimation {
+    pub sheet: Handle<SpriteSheet>,
+    pose: PlayerPose,
+    /// Position into the current clip's frames
+    frame: usize,
+    frame_timer: Timer,
+    /// The newest position the server sent
+    last_target: Option<Vec2>,
+    still_for: f32,
+    velocity: Vec2,
+}
+
+impl PlayerAnimation {
+    pub fn new(sheet: Handle<SpriteSheet>) -> Self {
+        PlayerAnimation {
+            sheet,
+            pose: PlayerPose::Idle,
+            frame: 0,
+            frame_timer: Timer::new(Duration::ZERO, true),
+            last_target: None,
+            still_for: f32::INFINITY,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    fn play(&mut self, pose: PlayerPose) {
+        self.pose = pose;
+        self.frame = 0;
+        self.frame_timer.reset();
+    }
+
+    /// Works out how fast the player is going from the newest position the
+    /// server sent, which is where they're being interpolated towards.
+    fn track(&mut self, target: Vec2, delta_seconds: f32) {
+        match self.last_target {
+            Some(last) if last != target => {
+                self.velocity = (target - last) / SERVER_UPDATE_INTERVAL;
+                self.still_for = 0.;
+            }
+            _ => {
+                self.still_for += delta_seconds;
+                if self.still_for >= WALK_GRACE {
+                    self.velocity = Vec2::ZERO;
+                }
+            }
+        }
+        self.last_target = Some(target);
+    }
+}
+
+/// How fast to play the walk animation for a player going at `velocity` while
+/// facing `facing`, relative to its `fps`. Negative when walking backwards,
+/// and zero when standing still.
+fn walk_rate(velocity: Vec2, facing: Vec2) -> f32 {
+    let speed = velocity.length();
+    if speed < MIN_WALK_SPEED {
+        return 0.;
+    }
+    let rate = (speed / WALK_CLIP_SPEED).clamp(MIN_WALK_RATE, MAX_WALK_RATE);
+    if velocity.dot(facing) < 0. {
+        -rate
+    } else {
+        rate
+    }
+}
+
+/// Picks each player's pose from how they're moving and whether they just
+/// fired, and steps through its frames. Walking keeps pace with the player's
+/// speed and runs backwards when they back away from where they aim. Firing
+/// plays once, then the player goes back to walking or standing.
+pub fn animate_player_sprites(
+    mut game_events: EventReader<GameEvent>,
+    time: Res<Time>,
+    sheets: Res<Assets<SpriteSheet>>,
+    mut player_query: Query<(
+        &UserId,
+        &Transform,
+        Option<&InterpolationBuffer>,
+        &mut PlayerAnimation,
+        &mut TextureAtlasSprite,
+        &mut Handle<TextureAtlas>,
+    )>,
+) {
+    let shooters: Vec<&UserId> = game_events
+        .iter()
+        .filter_map(|event| match event {
+            GameEvent::BulletFired {
+                owner: Some(owner), ..
+            } => Some(owner),
+            _ => None,
+        })
+        .collect();
+
+    for (user_id, transform, buffer, mut animation, mut sprite, mut atlas) in &mut player_query {
+        let target = buffer
+            .and_then(|buffer| buffer.0.back())
+            .unwrap_or(transform)
+            .translation
+            .truncate();
+        animation.track(target, time.delta_seconds());
+        let facing = (transform.rotation * Vec3::X).truncate();
+        let walk_rate = walk_rate(animation.velocity, facing);
+
+        let sheet = match sheets.get(&animation.sheet) {
+            Some(sheet) => sheet,
+            None => continue,
+        };
+        if *atlas != sheet.atlas {
+            *atlas = sheet.atlas.clone();
+        }
+
+        let moving_pose = if walk_rate != 0. {
+            PlayerPose::Walk
+        } else {
+            PlayerPose::Idle
+        };
+        if shooters.contains(&user_id) {
+            animation.play(PlayerPose::Fire);
+        } else if animation.pose != PlayerPose::Fire && animation.pose != moving_pose {
+            animation.play(moving_pose);
+        }
+
+        let clip = match sheet.clip(animation.pose.name()) {
+            Some(clip) => clip,
+            None => {
+                sprite.index = 0;
+                continue;
+            }
+        };
+        let rate = if animation.pose == PlayerPose::Walk {
+            walk_rate
+        } else {
+            1.
+        };
+        let frame_time = Duration::from_secs_f32(1. / (clip.fps * rate.abs()));
+        if animation.frame_timer.duration() != frame_time {
+            animation.frame_timer.set_duration(frame_time);
+        }
+        animation.frame_timer.tick(time.delta());
+        if animation.frame_timer.just_finished() {
+            animation.frame += 1;
+        }
+        let mut clip = clip;
+        if animation.frame >= clip.frames.len() {
+            if clip.looping {
+                animation.frame = 0;
+            } else {
+                // a finished one-off goes back to what the player is doing
+                animation.play(moving_pose);
+                clip = sheet.clip(moving_pose.name()).unwrap_or(clip);
+            }
+        }
+
+        let mut index = animation.frame.min(clip.frames.len() - 1);
+        if rate < 0. {
+            index = clip.frames.len() - 1 - index;
+        }
+        let frame = clip.frames[index];
+        if sprite.index != frame {
+            sprite.index = frame;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(json: &str) -> AtlasDescriptor {
+        serde_json::from_str(json).expect("Test descriptors should deserialize")
+    }
+
+    #[test]
+    fn the_bundled_sheet_is_valid() {
+        let bytes = std::fs::read("assets/sprites/player.atlas.json")
+            .expect("The bundled atlas should be readable");
+        let descriptor: AtlasDescriptor =
+            serde_json::from_slice(&bytes).expect("The bundled atlas should deserialize");
+        assert_eq!(descriptor.validate(), Ok(()));
+        assert_eq!(descriptor.image_size(), Vec2::new(204., 43.));
+        // walking has to have something to animate
+        assert!(descriptor.animations["walk"].frames.len() > 1);
+    }
+
+    #[test]
+    fn the_image_is_just_big_enough_without_a_size() {
+        let descriptor = descriptor(
+            r#"{"image": "sheet.png", "frames": [
+                {"x": 0, "y": 0, "width": 10, "height": 20},
+                {"x": 10, "y": 5, "width": 10, "height": 25}
+            ]}"#,
+        );
+        assert_eq!(descriptor.validate(), Ok(()));
+        assert_eq!(descriptor.image_size(), Vec2::new(20., 30.));
+    }
+
+    #[test]
+    fn frames_must_fit_in_the_image() {
+        let overhanging = descriptor(
+            r#"{"image": "sheet.png", "size": [32, 32], "frames": [
+                {"x": 0, "y": 0, "width": 32, "height": 32},
+                {"x": 16, "y": 0, "width": 32, "height": 32}
+            ]}"#,
+        );
+        assert_eq!(
+            overhanging.validate(),
+            Err("frame 1 doesn't fit in the 32x32 image".to_string())
+        );
+        let negative = descriptor(
+            r#"{"image": "sheet.png", "frames": [{"x": -4, "y": 0, "width": 8, "height": 8}]}"#,
+        );
+        assert!(negative.validate().is_err());
+    }
+
+    #[test]
+    fn sheets_need_frames() {
+        let empty = descriptor(r#"{"image": "sheet.png", "frames": []}"#);
+        assert_eq!(empty.validate(), Err("it has no frames".to_string()));
+        assert_eq!(empty.image_size(), Vec2::ZERO);
+
+        let no_clip_frames = descriptor(
+            r#"{"image": "sheet.png", "frames": [{"x": 0, "y": 0, "width": 8, "height": 8}],
+                "animations": {"walk": {"frames": []}}}"#,
+        );
+        assert_eq!(
+            no_clip_frames.validate(),
+            Err("animation \"walk\" has no frames".to_string())
+        );
+        let missing_frame = descriptor(
+            r#"{"image": "sheet.png", "frames": [{"x": 0, "y": 0, "width": 8, "height": 8}],
+                "animations": {"walk": {"frames": [0, 1]}}}"#,
+        );
+        assert!(missing_frame.validate().is_err());
+    }
+
+    #[test]
+    fn walking_keeps_pace_with_the_player() {
+        assert_eq!(walk_rate(Vec2::ZERO, Vec2::X), 0.);
+        assert_eq!(walk_rate(Vec2::new(WALK_CLIP_SPEED, 0.), Vec2::X), 1.);
+        assert_eq!(walk_rate(Vec2::new(0., WALK_CLIP_SPEED * 2.), Vec2::X), 2.);
+        assert_eq!(
+            walk_rate(Vec2::new(-WALK_CLIP_SPEED / 2., 0.), Vec2::X),
+            -0.5
+        );
+        // a shuffle still moves the legs, a respawn doesn't blur them
+        assert_eq!(walk_rate(Vec2::new(10., 0.), Vec2::X), MIN_WALK_RATE);
+        assert_eq!(walk_rate(Vec2::new(5000., 0.), Vec2::X), MAX_WALK_RATE);
+    }
+
+    #[test]
+    fn velocity_comes_from_server_positions() {
+        let mut animation = PlayerAnimation::new(Handle::default());
+        animation.track(Vec2::ZERO, 0.016);
+        assert_eq!(animation.velocity, Vec2::ZERO);
+
+        animation.track(Vec2::new(5., 0.), 0.016);
+        assert_eq!(animation.velocity, Vec2::new(100., 0.));
+        // the next update hasn't arrived yet
+        animation.track(Vec2::new(5., 0.), 0.016);
+        assert_eq!(animation.velocity, Vec2::new(100., 0.));
+
+        animation.track(Vec2::new(5., 0.), WALK_GRACE);
+        assert_eq!(animation.velocity, Vec2::ZERO);
+    }
+}
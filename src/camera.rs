@@ -0,0 +1,590 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    render::camera::ScalingMode,
+    window::WindowMode,
+};
+use clap::ValueEnum;
+
+use crate::{
+    components::{CurrentPlayer, MainCamera, UserId},
+    events::LocalPlayerEvent,
+    loading::GameAssets,
+    serialization::MapAsset,
+    spectator::Spectator,
+};
+
+pub struct CameraSettings {
+    pub smooth_time: f32,
+    pub look_ahead: f32,
+    pub deadzone: f32,
+    pub scaling: ViewScaling,
+    pub screen_shake: bool,
+}
+
+/// Follows the local player with a critically damped spring, so the camera
+/// settles on its target as fast as it can without overshooting and jitter in
+/// the player's position doesn't shake the screen.
+#[derive(Component)]
+pub struct CameraController {
+    /// Roughly how long the camera takes to catch up with its target, in seconds
+    pub smooth_time: f32,
+    /// How far the camera leans toward the cursor, in world units
+    pub look_ahead: f32,
+    /// How far the player can move from the camera's focus before it follows, in world units
+    pub deadzone: f32,
+    focus: Option<Vec2>,
+    position: Vec2,
+    velocity: Vec2,
+}
+
+impl CameraController {
+    pub fn new(smooth_time: f32, look_ahead: f32, deadzone: f32) -> Self {
+        CameraController {
+            smooth_time,
+            look_ahead,
+            deadzone,
+            focus: None,
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    /// Advances the camera by `delta_seconds` and returns where it should be
+    /// centered, before any clamping to the map. `aim` points from the player
+    /// toward the cursor and has a length between 0 and 1.
+    pub fn step(&mut self, player: Vec2, aim: Vec2, delta_seconds: f32) -> Vec2 {
+        let focus = match self.focus {
+            Some(focus) => {
+                let offset = player - focus;
+                if offset.length() > self.deadzone {
+                    player - offset.normalize() * self.deadzone
+                } else {
+                    focus
+                }
+            }
+            // snap to the player the first time, rather than sweeping in from the origin
+            None => {
+                self.position = player;
+                player
+            }
+        };
+        self.focus = Some(focus);
+
+        let target = focus + aim.clamp_length_max(1.) * self.look_ahead;
+        self.position = smooth_damp(
+            self.position,
+            target,
+            &mut self.velocity,
+            self.smooth_time,
+            delta_seconds,
+        );
+        self.position
+    }
+}
+
+/// Moves `current` toward `target` like a critically damped spring with the
+/// given `velocity`, which is updated in place. See Game Programming Gems 4,
+/// chapter 1.10.
+pub fn smooth_damp(
+    current: Vec2,
+    target: Vec2,
+    velocity: &mut Vec2,
+    smooth_time: f32,
+    delta_seconds: f32,
+) -> Vec2 {
+    if smooth_time <= 0. {
+        *velocity = Vec2::ZERO;
+        return target;
+    }
+
+    let omega = 2. / smooth_time;
+    let x = omega * delta_seconds;
+    let decay = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + omega * change) * delta_seconds;
+    *velocity = (*velocity - omega * temp) * decay;
+    target + (change + temp) * decay
+}
+
+pub fn setup_camera(mut commands: Commands, settings: Res<CameraSettings>) {
+    let mut camera = Camera2dBundle::default();
+    camera.projection.scaling_mode = settings.scaling.scaling_mode();
+
+    commands
+        .spawn_bundle(camera)
+        .insert(CameraZoom {
+            scale: 1.,
+            min_scale: 0.5,
+            max_scale: 2.,
+        })
+        .insert(CameraController::new(
+            settings.smooth_time,
+            settings.look_ahead,
+            settings.deadzone,
+        ))
+        .insert(CameraShake::new(settings.screen_shake))
+        .insert(MainCamera);
+}
+
+/// Where the cursor is relative to the middle of the window, scaled so the
+/// window's shorter half-extent has length 1. Unlike the cursor's world
+/// position this doesn't move along with the camera.
+fn cursor_offset(windows: &Windows) -> Vec2 {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return Vec2::ZERO,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => return Vec2::ZERO,
+    };
+    let half_size = Vec2::new(window.width(), window.height()) / 2.;
+    (cursor - half_size) / half_size.min_element()
+}
+
+pub fn update_camera(
+    current_player_query: Query<&Transform, (With<CurrentPlayer>, Without<Camera>)>,
+    player_query: Query<(&UserId, &Transform), (Without<CurrentPlayer>, Without<Camera>)>,
+    mut camera_query: Query<
+        (
+            &mut OrthographicProjection,
+            &mut Transform,
+            &mut CameraController,
+            &CameraZoom,
+            &mut CameraShake,
+        ),
+        (With<MainCamera>, Without<CurrentPlayer>),
+    >,
+    spectator: Res<Spectator>,
+    windows: Res<Windows>,
+    time: Res<Time>,
+
+    map_assets: ResMut<Assets<MapAsset>>,
+    assets: Res<GameAssets>,
+) {
+    let (mut projection, mut camera_transform, mut controller, zoom, mut shake) =
+        camera_query.single_mut();
+    let map = map_assets.get(&assets.map);
+
+    // can't use single here; CurrentPlayer might not have spawned yet
+    let local_player = current_player_query.iter().next();
+    let followed = if !spectator.is_spectating(local_player.is_some()) {
+        local_player.map(|transform| (transform.translation.truncate(), cursor_offset(&windows)))
+    } else if spectator.overview {
+        None
+    } else {
+        spectator.target.as_ref().and_then(|target| {
+            player_query
+                .iter()
+                .find(|(user_id, _)| *user_id == target)
+                .map(|(_, transform)| (transform.translation.truncate(), Vec2::ZERO))
+        })
+    };
+
+    let mut scale = zoom.scale;
+    let focus = match (followed, map) {
+        (Some(followed), _) => Some(followed),
+        // with nobody to follow, show the whole map
+        (None, Some(map)) => {
+            scale = overview_scale(&projection, map);
+            Some((map_center(map), Vec2::ZERO))
+        }
+        (None, None) => None,
+    };
+
+    if projection.scale != scale {
+        projection.scale = scale;
+    }
+
+    if let Some((position, aim)) = focus {
+        let position = controller.step(position, aim, time.delta_seconds());
+        // keep the camera's own z so it stays in front of every layer
+        camera_transform.translation.x = position.x;
+        camera_transform.translation.y = position.y;
+    }
+
+    if let Some(map) = map {
+        let clamped = clamp_to_map(
+            camera_transform.translation.truncate(),
+            visible_half_extents(&projection),
+            map,
+        );
+        camera_transform.translation.x = clamped.x;
+        camera_transform.translation.y = clamped.y;
+    }
+
+    // shake goes on last so it can nudge the view past the map's edges
+    shake.advance(time.delta_seconds());
+    let (offset, roll) = shake.displacement();
+    camera_transform.translation.x += offset.x;
+    camera_transform.translation.y += offset.y;
+    camera_transform.rotation = Quat::from_rotation_z(roll);
+}
+
+fn map_center(map: &MapAsset) -> Vec2 {
+    Vec2::new(
+        (map.left + map.right) as f32,
+        -(map.top + map.bottom) as f32,
+    ) * map.tileSize as f32
+        / 2.
+}
+
+/// The projection scale at which the whole map just fits in view.
+fn overview_scale(projection: &OrthographicProjection, map: &MapAsset) -> f32 {
+    let map_size = Vec2::new((map.right - map.left) as f32, (map.bottom - map.top) as f32)
+        * map.tileSize as f32;
+    let unscaled_size = Vec2::new(
+        projection.right - projection.left,
+        projection.top - projection.bottom,
+    );
+    (map_size / unscaled_size).max_element()
+}
+
+/// Trauma-based screen shake. Events add trauma, which wears off over time,
+/// and the camera shakes with the square of it so small knocks stay subtle.
+#[derive(Component)]
+pub struct CameraShake {
+    /// Turns shaking off entirely, for players who find it uncomfortable
+    pub enabled: bool,
+    /// How much trauma wears off per second
+    pub recovery: f32,
+    /// Largest offset at full trauma, in world units
+    pub max_offset: f32,
+    /// Largest roll at full trauma, in radians
+    pub max_roll: f32,
+    trauma: f32,
+    elapsed: f32,
+}
+
+impl CameraShake {
+    pub fn new(enabled: bool) -> Self {
+        CameraShake {
+            enabled,
+            recovery: 1.5,
+            max_offset: 12.,
+            max_roll: 0.03,
+            trauma: 0.,
+            elapsed: 0.,
+        }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        if self.enabled {
+            self.trauma = (self.trauma + amount).min(1.);
+        }
+    }
+
+    pub fn advance(&mut self, delta_seconds: f32) {
+        self.elapsed += delta_seconds;
+        self.trauma = (self.trauma - self.recovery * delta_seconds).max(0.);
+    }
+
+    /// The current offset and roll to apply on top of the camera's position.
+    pub fn displacement(&self) -> (Vec2, f32) {
+        if !self.enabled || self.trauma == 0. {
+            return (Vec2::ZERO, 0.);
+        }
+
+        let shake = self.trauma * self.trauma;
+        // a few out-of-phase sine waves per axis make a cheap, smooth noise
+        let noise = |seed: f32| {
+            let t = self.elapsed * 25.;
+            ((t + seed).sin() + 0.5 * (2.3 * t + 1.7 * seed).sin()) / 1.5
+        };
+        (
+            Vec2::new(noise(0.), noise(10.)) * self.max_offset * shake,
+            noise(20.) * self.max_roll * shake,
+        )
+    }
+}
+
+pub fn add_camera_trauma(
+    mut local_player_events: EventReader<LocalPlayerEvent>,
+    mut shake_query: Query<&mut CameraShake, With<MainCamera>>,
+) {
+    for event in local_player_events.iter() {
+        let trauma = match event {
+            LocalPlayerEvent::Fired => 0.15,
+            LocalPlayerEvent::Hit => 0.5,
+            LocalPlayerEvent::Removed => 0.8,
+        };
+        for mut shake in &mut shake_query {
+            shake.add_trauma(trauma);
+        }
+    }
+}
+
+/// Half the size of the world area the projection shows, at its current zoom.
+pub fn visible_half_extents(projection: &OrthographicProjection) -> Vec2 {
+    Vec2::new(
+        projection.right - projection.left,
+        projection.top - projection.bottom,
+    ) * projection.scale
+        / 2.
+}
+
+/// Moves a view centered on `center` so it doesn't show anything outside the
+/// map. Along any axis where the view is larger than the map, the map is
+/// centered instead.
+pub fn clamp_to_map(center: Vec2, half_extents: Vec2, map: &MapAsset) -> Vec2 {
+    let tile_size = map.tileSize as f32;
+    let map_min = Vec2::new(map.left as f32, -map.bottom as f32) * tile_size;
+    let map_max = Vec2::new(map.right as f32, -map.top as f32) * tile_size;
+
+    let clamp_axis = |center: f32, half_extent: f32, min: f32, max: f32| {
+        if max - min <= 2. * half_extent {
+            (min + max) / 2.
+        } else {
+            center.clamp(min + half_extent, max - half_extent)
+        }
+    };
+
+    Vec2::new(
+        clamp_axis(center.x, half_extents.x, map_min.x, map_max.x),
+        clamp_axis(center.y, half_extents.y, map_min.y, map_max.y),
+    )
+}
+
+/// Scroll-wheel zoom for a camera. `scale` multiplies the projection, so larger
+/// values show more of the world. It is kept apart from the projection's own
+/// scale so the zoom survives the spectator overview overriding it.
+#[derive(Component)]
+pub struct CameraZoom {
+    pub scale: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+// how many pixels of a touchpad scroll count as one notch of a mouse wheel
+const PIXELS_PER_LINE: f32 = 20.;
+const ZOOM_STEP: f32 = 1.1;
+
+pub fn zoom_camera(
+    mut scroll_events: EventReader<MouseWheel>,
+    mut camera_query: Query<(&mut CameraZoom, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    let lines: f32 = scroll_events
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if lines == 0. {
+        return;
+    }
+
+    for (mut zoom, mut projection) in &mut camera_query {
+        zoom.scale = (zoom.scale * ZOOM_STEP.powf(-lines)).clamp(zoom.min_scale, zoom.max_scale);
+        projection.scale = zoom.scale;
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ViewScaling {
+    /// Show at least 800x600 world units, more along the wider axis
+    Auto,
+    /// Always show 600 world units vertically
+    FixedHeight,
+    /// One world unit per pixel, so bigger windows see more
+    Window,
+}
+
+impl ViewScaling {
+    pub fn scaling_mode(self) -> ScalingMode {
+        match self {
+            ViewScaling::Auto => ScalingMode::Auto {
+                min_width: 800.,
+                min_height: 600.,
+            },
+            ViewScaling::FixedHeight => ScalingMode::FixedVertical(600.),
+            ViewScaling::Window => ScalingMode::WindowSize,
+        }
+    }
+}
+
+pub fn toggle_fullscreen(keyboard: Res<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    if !keyboard.just_pressed(KeyCode::F11) {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        let mode = match window.mode() {
+            WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+            _ => WindowMode::Windowed,
+        };
+        window.set_mode(mode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f32 = 1. / 60.;
+
+    /// The bundled map's size: 17x33 tiles of 64 units.
+    fn map() -> MapAsset {
+        MapAsset {
+            tileSize: 64,
+            top: 0,
+            left: 0,
+            bottom: 33,
+            right: 17,
+            walls: vec![],
+            floor: None,
+            decorations: vec![],
+        }
+    }
+
+    fn projection(scale: f32) -> OrthographicProjection {
+        OrthographicProjection {
+            left: -400.,
+            right: 400.,
+            bottom: -300.,
+            top: 300.,
+            scale,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn smooth_damp_never_overshoots() {
+        for delta_seconds in [FRAME, 0.1, 0.5] {
+            let target = Vec2::new(100., -50.);
+            let mut position = Vec2::ZERO;
+            let mut velocity = Vec2::ZERO;
+            for _ in 0..200 {
+                let next = smooth_damp(position, target, &mut velocity, 0.2, delta_seconds);
+                assert!(next.x >= position.x && next.x <= target.x + 1e-3);
+                assert!(next.y <= position.y && next.y >= target.y - 1e-3);
+                position = next;
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_damp_converges_on_the_target() {
+        let target = Vec2::new(300., 200.);
+        let mut position = Vec2::ZERO;
+        let mut velocity = Vec2::ZERO;
+        for _ in 0..120 {
+            position = smooth_damp(position, target, &mut velocity, 0.2, FRAME);
+        }
+        assert!(position.distance(target) < 0.5, "ended at {}", position);
+        assert!(velocity.length() < 5., "still moving at {}", velocity);
+    }
+
+    #[test]
+    fn no_smooth_time_snaps_to_the_target() {
+        let mut velocity = Vec2::new(10., 10.);
+        let target = Vec2::new(30., 40.);
+        assert_eq!(
+            smooth_damp(Vec2::ZERO, target, &mut velocity, 0., FRAME),
+            target
+        );
+        assert_eq!(velocity, Vec2::ZERO);
+
+        let mut controller = CameraController::new(0., 0., 0.);
+        controller.step(Vec2::ZERO, Vec2::ZERO, FRAME);
+        assert_eq!(
+            controller.step(Vec2::new(500., 0.), Vec2::ZERO, FRAME),
+            Vec2::new(500., 0.)
+        );
+    }
+
+    #[test]
+    fn the_deadzone_holds_the_focus() {
+        let mut controller = CameraController::new(0., 0., 50.);
+        assert_eq!(controller.step(Vec2::ZERO, Vec2::ZERO, FRAME), Vec2::ZERO);
+
+        // within the deadzone nothing moves
+        assert_eq!(
+            controller.step(Vec2::new(30., 0.), Vec2::ZERO, FRAME),
+            Vec2::ZERO
+        );
+        assert_eq!(
+            controller.step(Vec2::new(-20., 40.), Vec2::ZERO, FRAME),
+            Vec2::ZERO
+        );
+
+        // past it, the focus is dragged along at the deadzone's edge
+        assert_eq!(
+            controller.step(Vec2::new(80., 0.), Vec2::ZERO, FRAME),
+            Vec2::new(30., 0.)
+        );
+        assert_eq!(
+            controller.step(Vec2::new(60., 0.), Vec2::ZERO, FRAME),
+            Vec2::new(30., 0.)
+        );
+    }
+
+    #[test]
+    fn look_ahead_is_capped() {
+        let mut controller = CameraController::new(0., 120., 0.);
+        controller.step(Vec2::ZERO, Vec2::ZERO, FRAME);
+
+        let halfway = controller.step(Vec2::ZERO, Vec2::new(0.5, 0.), FRAME);
+        assert_eq!(halfway, Vec2::new(60., 0.));
+        for aim in [Vec2::new(1., 0.), Vec2::new(3., 4.), Vec2::new(-40., 0.)] {
+            let position = controller.step(Vec2::ZERO, aim, FRAME);
+            assert!((position.length() - 120.).abs() < 1e-3, "{}", position);
+        }
+    }
+
+    #[test]
+    fn the_first_step_starts_on_the_player() {
+        let mut controller = CameraController::new(0.3, 100., 20.);
+        let player = Vec2::new(400., -900.);
+        assert_eq!(controller.step(player, Vec2::ZERO, FRAME), player);
+    }
+
+    #[test]
+    fn visible_half_extents_follow_the_zoom() {
+        for (scale, half_extents) in [
+            (0.5, Vec2::new(200., 150.)),
+            (1., Vec2::new(400., 300.)),
+            (2., Vec2::new(800., 600.)),
+        ] {
+            assert_eq!(visible_half_extents(&projection(scale)), half_extents);
+        }
+    }
+
+    #[test]
+    fn views_are_kept_inside_the_map() {
+        let map = map();
+        let half_extents = visible_half_extents(&projection(1.));
+
+        // nowhere near an edge
+        let middle = Vec2::new(544., -1056.);
+        assert_eq!(clamp_to_map(middle, half_extents, &map), middle);
+        // past the top-left corner
+        assert_eq!(
+            clamp_to_map(Vec2::new(-100., 100.), half_extents, &map),
+            Vec2::new(400., -300.)
+        );
+        // past the bottom-right corner
+        assert_eq!(
+            clamp_to_map(Vec2::new(5000., -5000.), half_extents, &map),
+            Vec2::new(1088. - 400., -2112. + 300.)
+        );
+    }
+
+    #[test]
+    fn views_larger_than_the_map_center_it() {
+        let map = map();
+        // zoomed out, the view is wider than the map but not taller
+        let half_extents = visible_half_extents(&projection(2.));
+        assert_eq!(
+            clamp_to_map(Vec2::new(0., 0.), half_extents, &map),
+            Vec2::new(544., -600.)
+        );
+        // zoomed all the way out, it's bigger both ways
+        let half_extents = visible_half_extents(&projection(4.));
+        assert_eq!(
+            clamp_to_map(Vec2::new(0., 0.), half_extents, &map),
+            Vec2::new(544., -1056.)
+        );
+    }
+}
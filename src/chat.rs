@@ -0,0 +1,505 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*, window::ReceivedCharacter};
+use hathora_client_sdk::HathoraTransport;
+
+use crate::{
+    components::{InGameEntity, UserId},
+    connection::BackendError,
+    events::ChatReceived,
+    loading::GameAssets,
+    menu::text_style,
+    names::PlayerNames,
+    palette::player_color,
+    serialization::{ChatInput, CHAT_MESSAGE_TYPE},
+};
+
+pub const MAX_MESSAGE_LENGTH: usize = 200;
+const MAX_HISTORY: usize = 100;
+const VISIBLE_LINES: usize = 8;
+/// How long messages stay on screen while the chat is closed.
+const RECENT_FOR: Duration = Duration::from_secs(10);
+const SYSTEM_TEXT: Color = Color::rgb(0.75, 0.75, 0.75);
+const PANEL_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+
+/// Words masked out of chat messages. Only whole words are matched, so that
+/// names like Scunthorpe or Dickens come through untouched.
+const BLOCKED_WORDS: [&str; 20] = [
+    "fuck", "fucks", "fucked", "fucker", "fucking", "shit", "shits", "shitty", "bullshit", "cunt",
+    "cunts", "bitch", "bitches", "asshole", "assholes", "bastard", "bastards", "dick", "whore",
+    "whores",
+];
+
+pub struct ChatLine {
+    /// `None` for notices from the game itself
+    pub sender: Option<UserId>,
+    pub text: String,
+    pub received_at: Duration,
+}
+
+/// Allows at most `max` messages in any `window` of time.
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    sent: VecDeque<Duration>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        RateLimiter {
+            max,
+            window,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Records a message sent at `now` if that doesn't go over the limit.
+    pub fn try_send(&mut self, now: Duration) -> bool {
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.saturating_sub(*sent) >= self.window)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+pub struct Chat {
+    /// Whether the chat has the keyboard. Movement keys are ignored meanwhile
+    pub typing: bool,
+    pub draft: String,
+    pub history: VecDeque<ChatLine>,
+    /// How many lines back from the newest the history is scrolled
+    pub scroll: usize,
+    pub rate_limiter: RateLimiter,
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Chat {
+            typing: false,
+            draft: String::new(),
+            history: VecDeque::new(),
+            scroll: 0,
+            rate_limiter: RateLimiter::new(3, Duration::from_secs(5)),
+        }
+    }
+}
+
+impl Chat {
+    pub fn push(&mut self, sender: Option<UserId>, text: String, received_at: Duration) {
+        self.history.push_back(ChatLine {
+            sender,
+            text,
+            received_at,
+        });
+        if self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        // stay on the same lines while scrolled back
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.max_scroll());
+        }
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.history.len().saturating_sub(VISIBLE_LINES)
+    }
+
+    /// The lines to show: a page of scrollback while typing, otherwise only
+    /// the newest few that arrived recently.
+    fn visible_lines(&self, now: Duration) -> impl Iterator<Item = &ChatLine> {
+        let end = self.history.len() - self.scroll.min(self.history.len());
+        let start = end.saturating_sub(VISIBLE_LINES);
+        let typing = self.typing;
+        self.history
+            .range(start..end)
+            .filter(move |line| typing || now.saturating_sub(line.received_at) < RECENT_FOR)
+    }
+}
+
+/// Drops control characters, caps the length and masks blocked words, so
+/// what's shown is the same whoever sent it.
+pub fn sanitize_message(message: &str) -> String {
+    let message: String = message
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control())
+        .take(MAX_MESSAGE_LENGTH)
+        .collect();
+
+    let mut sanitized = String::with_capacity(message.len());
+    let mut word = String::new();
+    for c in message.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        if BLOCKED_WORDS.contains(&word.to_lowercase().as_str()) {
+            let mut chars = word.chars();
+            sanitized.extend(chars.next());
+            sanitized.extend(chars.map(|_| '*'));
+        } else {
+            sanitized.push_str(&word);
+        }
+        word.clear();
+        sanitized.push(c);
+    }
+    sanitized.pop();
+    sanitized.trim().to_string()
+}
+
+pub fn send_chat_message(
+    transport: &mut dyn HathoraTransport,
+    message: String,
+) -> Result<(), BackendError> {
+    let input = ChatInput {
+        serialized_type: CHAT_MESSAGE_TYPE,
+        message,
+    };
+    let message = serde_json::to_vec(&input).expect("Serialization should work");
+    Ok(transport.write_message(message)?)
+}
+
+#[derive(Component)]
+pub struct ChatHistoryText;
+
+#[derive(Component)]
+pub struct ChatInputText;
+
+#[derive(Component)]
+pub struct ChatInputLine;
+
+pub fn spawn_chat_panel(assets: Res<GameAssets>, mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(40.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(420.0), Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(InGameEntity)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section("", text_style(&assets, 16.0, Color::WHITE))
+                        .with_style(Style {
+                            max_size: Size::new(Val::Px(420.0), Val::Undefined),
+                            ..default()
+                        }),
+                )
+                .insert(ChatHistoryText);
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        padding: UiRect::all(Val::Px(4.0)),
+                        margin: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Px(4.0), Val::Px(0.0)),
+                        display: Display::None,
+                        ..default()
+                    },
+                    color: PANEL_BACKGROUND.into(),
+                    ..default()
+                })
+                .insert(ChatInputLine)
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(TextBundle::from_section(
+                            "",
+                            text_style(&assets, 16.0, Color::WHITE),
+                        ))
+                        .insert(ChatInputText);
+                });
+        });
+}
+
+/// Enter opens the chat and sends what's typed, or closes it if nothing is.
+/// Esc closes it and throws the draft away. Page Up and Page Down scroll
+/// through the history while it's open.
+pub fn chat_input(
+    keyboard: Res<Input<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    time: Res<Time>,
+    client_user_id: Res<UserId>,
+    mut transport: ResMut<Box<dyn HathoraTransport>>,
+    mut chat: ResMut<Chat>,
+) {
+    if !chat.typing {
+        // the characters typed while closed are game controls
+        received_characters.clear();
+        if keyboard.just_pressed(KeyCode::Return) {
+            chat.typing = true;
+        }
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Escape) {
+        received_characters.clear();
+        chat.typing = false;
+        chat.scroll = 0;
+        chat.draft.clear();
+        return;
+    }
+
+    for event in received_characters.iter() {
+        if !event.char.is_control() && chat.draft.chars().count() < MAX_MESSAGE_LENGTH {
+            chat.draft.push(event.char);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Back) {
+        chat.draft.pop();
+    }
+    if keyboard.just_pressed(KeyCode::PageUp) {
+        chat.scroll = (chat.scroll + VISIBLE_LINES).min(chat.max_scroll());
+    }
+    if keyboard.just_pressed(KeyCode::PageDown) {
+        chat.scroll = chat.scroll.saturating_sub(VISIBLE_LINES);
+    }
+
+    if !keyboard.just_pressed(KeyCode::Return) {
+        return;
+    }
+    let now = time.time_since_startup();
+    let message = sanitize_message(&chat.draft);
+    chat.typing = false;
+    chat.scroll = 0;
+    if message.is_empty() {
+        chat.draft.clear();
+        return;
+    }
+
+    if !chat.rate_limiter.try_send(now) {
+        // keep the draft so it can be sent again in a moment
+        chat.push(None, "You're sending messages too quickly".to_string(), now);
+        return;
+    }
+    chat.draft.clear();
+    match send_chat_message(&mut **transport, message.clone()) {
+        Ok(()) => chat.push(Some(client_user_id.clone()), message, now),
+        Err(e) => {
+            warn!("Failed to send a chat message. Error was {}", e);
+            chat.push(None, "Couldn't send your message".to_string(), now);
+        }
+    }
+}
+
+/// Runs a system only while the chat doesn't have the keyboard, for keys
+/// that mean something else while typing.
+pub fn chat_closed(chat: Res<Chat>) -> ShouldRun {
+    if chat.typing {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+pub fn receive_chat_messages(
+    mut chat_events: EventReader<ChatReceived>,
+    time: Res<Time>,
+    client_user_id: Res<UserId>,
+    mut chat: ResMut<Chat>,
+) {
+    for event in chat_events.iter() {
+        // our own messages were shown as soon as they were sent
+        if event.sender == *client_user_id {
+            continue;
+        }
+        let message = sanitize_message(&event.message);
+        if !message.is_empty() {
+            chat.push(
+                Some(event.sender.clone()),
+                message,
+                time.time_since_startup(),
+            );
+        }
+    }
+}
+
+pub fn update_chat_panel(
+    assets: Res<GameAssets>,
+    time: Res<Time>,
+    chat: Res<Chat>,
+    player_names: Res<PlayerNames>,
+    mut shown_lines: Local<usize>,
+    mut history_query: Query<&mut Text, (With<ChatHistoryText>, Without<ChatInputText>)>,
+    mut input_query: Query<&mut Text, (With<ChatInputText>, Without<ChatHistoryText>)>,
+    mut input_line_query: Query<&mut Style, With<ChatInputLine>>,
+) {
+    let now = time.time_since_startup();
+    // recent lines disappear over time, not only when the chat changes
+    let line_count = chat.visible_lines(now).count();
+    if !chat.is_changed() && *shown_lines == line_count {
+        return;
+    }
+    *shown_lines = line_count;
+
+    let mut sections = vec![];
+    for line in chat.visible_lines(now) {
+        if !sections.is_empty() {
+            sections.push(TextSection::new(
+                "\n",
+                text_style(&assets, 16.0, Color::WHITE),
+            ));
+        }
+        match &line.sender {
+            Some(sender) => {
+                sections.push(TextSection::new(
+                    format!("{}: ", player_names.get(sender)),
+                    text_style(&assets, 16.0, player_color(sender)),
+                ));
+                sections.push(TextSection::new(
+                    line.text.clone(),
+                    text_style(&assets, 16.0, Color::WHITE),
+                ));
+            }
+            None => sections.push(TextSection::new(
+                line.text.clone(),
+                text_style(&assets, 16.0, SYSTEM_TEXT),
+            )),
+        }
+    }
+    for mut text in &mut history_query {
+        text.sections = sections.clone();
+    }
+
+    for mut text in &mut input_query {
+        text.sections[0].value = format!("> {}_", chat.draft);
+    }
+    for mut style in &mut input_line_query {
+        style.display = if chat.typing {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
+pub fn reset_chat(mut chat: ResMut<Chat>) {
+    *chat = Chat::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::FakeTransport;
+
+    #[test]
+    fn chat_messages_are_sent_as_type_3() {
+        let mut transport = FakeTransport::default();
+        send_chat_message(&mut transport, "hello there".to_string()).expect("Sending should work");
+        assert_eq!(
+            transport.written_messages(),
+            vec![r#"{"type":3,"message":"hello there"}"#]
+        );
+    }
+
+    #[test]
+    fn three_messages_fit_in_five_seconds() {
+        let mut chat = Chat::default();
+        let limiter = &mut chat.rate_limiter;
+        assert!(limiter.try_send(Duration::from_secs(0)));
+        assert!(limiter.try_send(Duration::from_secs(1)));
+        assert!(limiter.try_send(Duration::from_secs(2)));
+        assert!(!limiter.try_send(Duration::from_secs(3)));
+        // refused messages don't count towards the limit
+        assert!(!limiter.try_send(Duration::from_millis(4999)));
+        assert!(limiter.try_send(Duration::from_secs(5)));
+        assert!(!limiter.try_send(Duration::from_millis(5500)));
+        assert!(limiter.try_send(Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn sanitizing_masks_blocked_words_inside_other_words() {
+        assert_eq!(sanitize_message("well SHIT happens"), "well S*** happens");
+        assert_eq!(sanitize_message("bullshit!"), "b*******!");
+        assert_eq!(sanitize_message("good game"), "good game");
+    }
+
+    #[test]
+    fn sanitizing_leaves_words_that_only_contain_blocked_ones() {
+        for message in [
+            "Scunthorpe United",
+            "reading Dickens",
+            "Shitake? no, shiitake",
+            "cockpit",
+        ] {
+            assert_eq!(sanitize_message(message), message);
+        }
+        assert_eq!(sanitize_message("dick dickens"), "d*** dickens");
+    }
+
+    #[test]
+    fn sanitizing_drops_control_characters_and_caps_the_length() {
+        assert_eq!(sanitize_message("  a\tb\nc\u{7}d  "), "a b cd");
+        assert_eq!(sanitize_message("\u{1b}[2J"), "[2J");
+        let long = "a".repeat(MAX_MESSAGE_LENGTH + 50);
+        assert_eq!(sanitize_message(&long).chars().count(), MAX_MESSAGE_LENGTH);
+    }
+
+    #[derive(Default)]
+    struct Quits(usize);
+
+    fn quit_on_esc(keyboard: Res<Input<KeyCode>>, mut quits: ResMut<Quits>) {
+        if keyboard.just_pressed(KeyCode::Escape) {
+            quits.0 += 1;
+        }
+    }
+
+    fn press(app: &mut App, key: KeyCode) {
+        let mut keyboard = app.world.resource_mut::<Input<KeyCode>>();
+        // let go of everything so the key counts as just pressed again
+        keyboard.reset_all();
+        keyboard.press(key);
+    }
+
+    #[test]
+    fn esc_closes_the_chat_instead_of_the_game() {
+        let transport: Box<dyn HathoraTransport> = Box::new(FakeTransport::default());
+        let mut app = App::new();
+        app.insert_resource(transport)
+            .insert_resource(UserId("me".to_string()))
+            .init_resource::<Chat>()
+            .init_resource::<Quits>()
+            .init_resource::<Input<KeyCode>>()
+            .insert_resource(Time::default())
+            .add_event::<ReceivedCharacter>()
+            // scheduled like close_on_esc is in the game
+            .add_system(
+                quit_on_esc
+                    .with_run_criteria(chat_closed)
+                    .before(chat_input),
+            )
+            .add_system(chat_input);
+
+        press(&mut app, KeyCode::Return);
+        app.update();
+        assert!(app.world.resource::<Chat>().typing);
+        app.world.resource_mut::<Chat>().draft = "half a thou".to_string();
+
+        press(&mut app, KeyCode::Escape);
+        app.update();
+        let chat = app.world.resource::<Chat>();
+        assert!(!chat.typing);
+        assert!(chat.draft.is_empty());
+        assert_eq!(app.world.resource::<Quits>().0, 0);
+
+        // with the chat closed Esc is back to quitting
+        press(&mut app, KeyCode::Escape);
+        app.update();
+        assert_eq!(app.world.resource::<Quits>().0, 1);
+    }
+}
@@ -5,6 +5,16 @@ use bevy::prelude::*;
 #[derive(Component, Eq, PartialEq, Hash, Clone, Debug)]
 pub struct UserId(pub String);
 
+impl UserId {
+    /// FNV-1a of the ID. `DefaultHasher` may change between Rust releases, and
+    /// anything derived from this has to match on every client.
+    pub fn stable_hash(&self) -> u64 {
+        self.0.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+}
+
 #[derive(Component)]
 pub struct BulletId(pub i32);
 
@@ -15,4 +25,14 @@ pub struct MainCamera;
 pub struct InterpolationBuffer(pub VecDeque<Transform>);
 
 #[derive(Component)]
-pub struct CurrentPlayer;
\ No newline at end of file
+pub struct CurrentPlayer;
+
+#[derive(Component)]
+pub struct RoomIdText;
+
+#[derive(Component)]
+pub struct CopyRoomIdButton;
+
+/// Anything that belongs to the current room and goes away when leaving it.
+#[derive(Component)]
+pub struct InGameEntity;
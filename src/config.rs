@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+/// Where the game keeps a file of its own between runs, or `None` if there's
+/// no config directory to put it in.
+pub fn config_path(file_name: &str) -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| std::env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("topdown-shooter").join(file_name))
+}
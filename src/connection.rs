@@ -0,0 +1,590 @@
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use hathora_client_sdk::{HathoraClient, HathoraTransport, HathoraTransportType, LoginResponse};
+
+use crate::{
+    components::UserId,
+    menu::{ConnectRequest, ConnectionError, ConnectionStatusText},
+    names::{Nickname, PlayerNames},
+    systems::RoomId,
+    AppState,
+};
+
+pub const DEFAULT_APP_ID: &str = "e2d8571eb89af72f2abbe909def5f19bc4dad0cd475cce5f5b6e9018017d1f1c";
+
+const COORDINATOR_HOST: &str = "coordinator.hathora.dev";
+
+/// Gives up on a connection attempt that hasn't finished by then.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The calls needed to get into a room. They block, so they're only ever
+/// made from a task. Anything implementing this, like a fake that fails on
+/// demand, can stand in for Hathora by replacing the `Backend` resource.
+pub trait GameBackend: Send + Sync {
+    fn log_in(&self) -> Result<String, BackendError>;
+
+    fn log_in_with_nickname(&self, nickname: &str) -> Result<String, BackendError>;
+
+    fn create_room(&self, token: &str) -> Result<String, BackendError>;
+
+    fn user_id(&self, token: &str) -> Result<String, BackendError>;
+
+    fn connect(
+        &self,
+        token: &str,
+        room_id: &str,
+    ) -> Result<Box<dyn HathoraTransport>, BackendError>;
+}
+
+/// The backend for the app passed with `--app-id`, and a way to reach the
+/// other apps join links can point at.
+pub struct Backend {
+    pub default: Arc<dyn GameBackend>,
+    pub other_app: Box<dyn Fn(&str) -> Arc<dyn GameBackend> + Send + Sync>,
+}
+
+impl Backend {
+    pub fn hathora(app_id: String) -> Self {
+        Backend {
+            default: Arc::new(HathoraBackend::new(app_id)),
+            other_app: Box::new(|app_id| Arc::new(HathoraBackend::new(app_id.to_string()))),
+        }
+    }
+
+    pub fn for_app(&self, app_id: &str) -> Arc<dyn GameBackend> {
+        (self.other_app)(app_id)
+    }
+}
+
+pub struct HathoraBackend {
+    app_id: String,
+    client: HathoraClient,
+    http: reqwest::blocking::Client,
+}
+
+impl HathoraBackend {
+    pub fn new(app_id: String) -> Self {
+        HathoraBackend {
+            client: HathoraClient::new(app_id.clone(), Some(COORDINATOR_HOST.to_string())),
+            app_id,
+            http: reqwest::blocking::Client::new(),
+        }
+    }
+}
+
+impl GameBackend for HathoraBackend {
+    fn log_in(&self) -> Result<String, BackendError> {
+        Ok(self.client.login_anonymous()?)
+    }
+
+    fn log_in_with_nickname(&self, nickname: &str) -> Result<String, BackendError> {
+        // the SDK's login_nickname doesn't send a nickname, so the request is made here
+        let login_url = format!(
+            "https://{}/{}/login/nickname",
+            COORDINATOR_HOST, self.app_id
+        );
+        let response: LoginResponse = self
+            .http
+            .post(login_url)
+            .json(&serde_json::json!({ "nickname": nickname }))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        Ok(response.token)
+    }
+
+    fn create_room(&self, token: &str) -> Result<String, BackendError> {
+        Ok(self.client.create(token, vec![])?)
+    }
+
+    fn user_id(&self, token: &str) -> Result<String, BackendError> {
+        // the SDK panics on a token without a payload rather than returning an error
+        if token.split('.').nth(1).is_none() {
+            return Err("it has no payload".into());
+        }
+        Ok(HathoraClient::get_user_from_token(token)?)
+    }
+
+    fn connect(
+        &self,
+        token: &str,
+        room_id: &str,
+    ) -> Result<Box<dyn HathoraTransport>, BackendError> {
+        // the SDK panics instead of returning an error when the socket can't be opened
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.client
+                .connect(token, room_id, HathoraTransportType::WebSocket)
+        }));
+        match result {
+            Ok(transport) => Ok(transport?),
+            Err(_) => Err("the web socket couldn't be opened".into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    LogIn(BackendError),
+    CreateRoom(BackendError),
+    InvalidToken(BackendError),
+    Join {
+        room_id: String,
+        source: BackendError,
+    },
+    TimedOut(ConnectStage),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::LogIn(e) => write!(f, "logging in failed: {}", e),
+            ConnectError::CreateRoom(e) => write!(f, "creating a room failed: {}", e),
+            ConnectError::InvalidToken(e) => {
+                write!(f, "the login token couldn't be decoded: {}", e)
+            }
+            ConnectError::Join { room_id, source } => {
+                write!(f, "joining room {} failed: {}", room_id, source)
+            }
+            ConnectError::TimedOut(stage) => write!(
+                f,
+                "timed out after {} seconds while {}",
+                CONNECT_TIMEOUT.as_secs(),
+                stage.description()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConnectStage {
+    LoggingIn,
+    CreatingRoom,
+    Joining,
+}
+
+impl ConnectStage {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ConnectStage::LoggingIn => "logging in",
+            ConnectStage::CreatingRoom => "creating a room",
+            ConnectStage::Joining => "joining the room",
+        }
+    }
+}
+
+pub struct Connection {
+    room_id: String,
+    user_id: String,
+    nickname: Option<String>,
+    transport: Box<dyn HathoraTransport>,
+}
+
+/// Logs in, creates a room if none was given and opens the transport,
+/// reporting each step through `stage`. Apps without nickname logins are
+/// logged into anonymously, and the nickname is only shown locally.
+pub fn log_in_and_connect(
+    backend: &dyn GameBackend,
+    room_id: Option<String>,
+    nickname: Option<String>,
+    stage: &Mutex<ConnectStage>,
+) -> Result<Connection, ConnectError> {
+    let set_stage = |next| {
+        *stage
+            .lock()
+            .expect("Connection stage lock shouldn't be poisoned") = next;
+    };
+
+    set_stage(ConnectStage::LoggingIn);
+    let token = match &nickname {
+        Some(nickname) => backend.log_in_with_nickname(nickname).or_else(|e| {
+            warn!(
+                "Nickname login failed, logging in anonymously. Error was {}",
+                e
+            );
+            backend.log_in()
+        }),
+        None => backend.log_in(),
+    }
+    .map_err(ConnectError::LogIn)?;
+
+    let room_id = match room_id {
+        Some(room_id) => room_id,
+        None => {
+            debug!("No room provided, creating one");
+            set_stage(ConnectStage::CreatingRoom);
+            backend
+                .create_room(&token)
+                .map_err(ConnectError::CreateRoom)?
+        }
+    };
+
+    let user_id = backend
+        .user_id(&token)
+        .map_err(ConnectError::InvalidToken)?;
+
+    set_stage(ConnectStage::Joining);
+    let transport = backend
+        .connect(&token, &room_id)
+        .map_err(|source| ConnectError::Join {
+            room_id: room_id.clone(),
+            source,
+        })?;
+
+    Ok(Connection {
+        room_id,
+        user_id,
+        nickname,
+        transport,
+    })
+}
+
+pub struct PendingConnection {
+    task: Task<Result<Connection, ConnectError>>,
+    stage: Arc<Mutex<ConnectStage>>,
+    timeout: Timer,
+}
+
+pub fn start_connecting(
+    backend: Res<Backend>,
+    request: Res<ConnectRequest>,
+    nickname: Res<Nickname>,
+    mut commands: Commands,
+) {
+    let backend = match &request.app_id {
+        Some(app_id) => backend.for_app(app_id),
+        None => backend.default.clone(),
+    };
+    let room_id = request.room_id.clone();
+    let nickname = nickname.0.clone();
+    let stage = Arc::new(Mutex::new(ConnectStage::LoggingIn));
+
+    let task_stage = stage.clone();
+    let task = AsyncComputeTaskPool::get()
+        .spawn(async move { log_in_and_connect(backend.as_ref(), room_id, nickname, &task_stage) });
+
+    commands.insert_resource(PendingConnection {
+        task,
+        stage,
+        timeout: Timer::new(CONNECT_TIMEOUT, false),
+    });
+}
+
+pub fn poll_connection(
+    pending: Option<ResMut<PendingConnection>>,
+    time: Res<Time>,
+    mut state: ResMut<State<AppState>>,
+    mut player_names: ResMut<PlayerNames>,
+    mut text_query: Query<&mut Text, With<ConnectionStatusText>>,
+    mut commands: Commands,
+) {
+    let mut pending = match pending {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    let stage = *pending
+        .stage
+        .lock()
+        .expect("Connection stage lock shouldn't be poisoned");
+    pending.timeout.tick(time.delta());
+
+    let result = if pending.timeout.finished() {
+        // the task is cancelled when it's dropped below, though a request that
+        // is already in flight still runs to completion in the background
+        Err(ConnectError::TimedOut(stage))
+    } else if let Some(result) = future::block_on(future::poll_once(&mut pending.task)) {
+        result
+    } else {
+        let status = format!(
+            "{}... {}s",
+            capitalize(stage.description()),
+            pending.timeout.elapsed().as_secs()
+        );
+        for mut text in &mut text_query {
+            if text.sections[0].value != status {
+                text.sections[0].value = status.clone();
+            }
+        }
+        return;
+    };
+    commands.remove_resource::<PendingConnection>();
+
+    let next_state = match result {
+        Ok(connection) => {
+            commands.insert_resource(RoomId(connection.room_id));
+            let user_id = UserId(connection.user_id);
+            if let Some(nickname) = connection.nickname {
+                player_names.insert(user_id.clone(), nickname);
+            }
+            commands.insert_resource(user_id);
+            commands.insert_resource(connection.transport);
+            AppState::InGame
+        }
+        Err(e) => {
+            error!("Failed to join a room. Error was {}", e);
+            commands.insert_resource(ConnectionError(e));
+            AppState::MainMenu
+        }
+    };
+
+    if let Err(e) = state.set(next_state) {
+        warn!("Couldn't switch to {:?}. Error was {:?}", next_state, e);
+    }
+}
+
+/// Drops an attempt that was cancelled from the connecting screen.
+pub fn cancel_connection(mut commands: Commands) {
+    commands.remove_resource::<PendingConnection>();
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::TaskPool;
+
+    use super::*;
+    use crate::fakes::{FakeBackend, FAKE_ROOM_ID, FAKE_TOKEN, FAKE_USER_ID};
+
+    fn connect(
+        backend: &FakeBackend,
+        room_id: Option<&str>,
+        nickname: Option<&str>,
+    ) -> Result<Connection, ConnectError> {
+        let stage = Mutex::new(ConnectStage::Joining);
+        log_in_and_connect(
+            backend,
+            room_id.map(str::to_string),
+            nickname.map(str::to_string),
+            &stage,
+        )
+    }
+
+    #[test]
+    fn connecting_creates_a_room_when_none_is_given() {
+        let backend = FakeBackend::default();
+        let connection = connect(&backend, None, None).expect("Connecting should succeed");
+        assert_eq!(connection.room_id, FAKE_ROOM_ID);
+        assert_eq!(connection.user_id, FAKE_USER_ID);
+        assert_eq!(
+            backend.call_names(),
+            ["log_in", "create_room", "user_id", "connect"]
+        );
+
+        let backend = FakeBackend::default();
+        let connection =
+            connect(&backend, Some("given-room"), None).expect("Connecting should succeed");
+        assert_eq!(connection.room_id, "given-room");
+        assert_eq!(backend.call_names(), ["log_in", "user_id", "connect"]);
+    }
+
+    #[test]
+    fn stages_are_reported_as_they_start() {
+        let stage = Arc::new(Mutex::new(ConnectStage::Joining));
+        let backend = FakeBackend {
+            stage: Some(stage.clone()),
+            ..default()
+        };
+        log_in_and_connect(&backend, None, None, &stage).expect("Connecting should succeed");
+
+        let calls = backend
+            .calls
+            .lock()
+            .expect("Call list lock shouldn't be poisoned")
+            .clone();
+        assert_eq!(
+            calls,
+            [
+                ("log_in", Some(ConnectStage::LoggingIn)),
+                ("create_room", Some(ConnectStage::CreatingRoom)),
+                ("user_id", Some(ConnectStage::CreatingRoom)),
+                ("connect", Some(ConnectStage::Joining)),
+            ]
+        );
+    }
+
+    #[test]
+    fn nickname_logins_fall_back_to_anonymous() {
+        let backend = FakeBackend::default();
+        let connection =
+            connect(&backend, Some("room"), Some("Alice")).expect("Connecting should succeed");
+        assert_eq!(connection.nickname.as_deref(), Some("Alice"));
+        assert_eq!(
+            backend.call_names(),
+            ["log_in_with_nickname", "user_id", "connect"]
+        );
+
+        let backend = FakeBackend {
+            fail_nickname_login: true,
+            ..default()
+        };
+        let connection =
+            connect(&backend, Some("room"), Some("Alice")).expect("Connecting should succeed");
+        // the name is still shown locally
+        assert_eq!(connection.nickname.as_deref(), Some("Alice"));
+        assert_eq!(
+            backend.call_names(),
+            ["log_in_with_nickname", "log_in", "user_id", "connect"]
+        );
+    }
+
+    #[test]
+    fn each_failing_step_has_its_own_error() {
+        let backend = FakeBackend {
+            fail_nickname_login: true,
+            fail_login: true,
+            ..default()
+        };
+        assert!(matches!(
+            connect(&backend, None, Some("Alice")),
+            Err(ConnectError::LogIn(_))
+        ));
+
+        let backend = FakeBackend {
+            fail_create_room: true,
+            ..default()
+        };
+        assert!(matches!(
+            connect(&backend, None, None),
+            Err(ConnectError::CreateRoom(_))
+        ));
+
+        let backend = FakeBackend {
+            bad_token: true,
+            ..default()
+        };
+        assert!(matches!(
+            connect(&backend, Some("room"), None),
+            Err(ConnectError::InvalidToken(_))
+        ));
+        assert_eq!(backend.call_names(), ["log_in", "user_id"]);
+
+        let backend = FakeBackend {
+            fail_connect: true,
+            ..default()
+        };
+        match connect(&backend, Some("room"), None) {
+            Err(ConnectError::Join { room_id, .. }) => assert_eq!(room_id, "room"),
+            other => panic!("expected a join error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn slow_connections_time_out() {
+        AsyncComputeTaskPool::init(TaskPool::new);
+        let mut app = App::new();
+        app.insert_resource(Time::default())
+            .insert_resource(PlayerNames::default())
+            .add_state(AppState::Connecting)
+            .add_system(poll_connection);
+        // let the initial state settle before anything tries to change it
+        app.update();
+        app.insert_resource(PendingConnection {
+            task: AsyncComputeTaskPool::get().spawn(future::pending()),
+            stage: Arc::new(Mutex::new(ConnectStage::CreatingRoom)),
+            timeout: Timer::new(Duration::ZERO, false),
+        });
+
+        app.update();
+
+        assert!(app.world.get_resource::<PendingConnection>().is_none());
+        assert!(matches!(
+            app.world.resource::<ConnectionError>().0,
+            ConnectError::TimedOut(ConnectStage::CreatingRoom)
+        ));
+        // the state switches over on the next frame
+        app.update();
+        assert_eq!(
+            app.world.resource::<State<AppState>>().current(),
+            &AppState::MainMenu
+        );
+    }
+
+    #[test]
+    fn join_links_connect_through_the_backend_for_their_app() {
+        AsyncComputeTaskPool::init(TaskPool::new);
+        let default_app = Arc::new(FakeBackend::default());
+        let other_app = Arc::new(FakeBackend::default());
+        let requested_apps = Arc::new(Mutex::new(vec![]));
+
+        let mut app = App::new();
+        let (other, requested) = (other_app.clone(), requested_apps.clone());
+        app.insert_resource(Backend {
+            default: default_app.clone(),
+            other_app: Box::new(move |app_id| {
+                requested
+                    .lock()
+                    .expect("Requested apps lock shouldn't be poisoned")
+                    .push(app_id.to_string());
+                other.clone()
+            }),
+        })
+        .insert_resource(ConnectRequest {
+            room_id: Some("linked-room".to_string()),
+            app_id: Some("other-app".to_string()),
+        })
+        .insert_resource(Nickname(None))
+        .insert_resource(Time::default())
+        .insert_resource(PlayerNames::default())
+        .add_state(AppState::Connecting)
+        .add_system_set(SystemSet::on_enter(AppState::Connecting).with_system(start_connecting))
+        .add_system_set(SystemSet::on_update(AppState::Connecting).with_system(poll_connection));
+
+        for _ in 0..100 {
+            app.update();
+            if app.world.resource::<State<AppState>>().current() == &AppState::InGame {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(
+            app.world.resource::<State<AppState>>().current(),
+            &AppState::InGame
+        );
+        assert_eq!(app.world.resource::<RoomId>().0, "linked-room");
+        assert_eq!(
+            *requested_apps
+                .lock()
+                .expect("Requested apps lock shouldn't be poisoned"),
+            ["other-app"]
+        );
+        assert_eq!(other_app.call_names(), ["log_in", "user_id", "connect"]);
+        assert!(default_app.call_names().is_empty());
+    }
+
+    #[test]
+    fn malformed_tokens_are_errors_not_panics() {
+        let backend = HathoraBackend::new(DEFAULT_APP_ID.to_string());
+        assert!(backend.user_id("not-a-jwt").is_err());
+        assert!(backend.user_id("").is_err());
+        assert!(backend.user_id("a.!!!.c").is_err());
+        // the fake's token is one the real backend can read too
+        assert_eq!(
+            backend
+                .user_id(FAKE_TOKEN)
+                .expect("Fake token should decode"),
+            FAKE_USER_ID
+        );
+    }
+}
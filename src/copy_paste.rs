@@ -0,0 +1,135 @@
+use std::sync::Mutex;
+
+use clipboard::{ClipboardContext, ClipboardProvider};
+
+pub type ClipboardError = Box<dyn std::error::Error + Send + Sync>;
+
+pub trait Clipboard: Send + Sync {
+    fn get(&self) -> Result<String, ClipboardError>;
+
+    fn set(&self, contents: String) -> Result<(), ClipboardError>;
+}
+
+pub struct GameClipboard(pub Box<dyn Clipboard>);
+
+/// The desktop clipboard. Opening it fails on headless machines and on
+/// Wayland sessions without X, which is reported as an error, not a panic.
+pub struct SystemClipboard;
+
+impl SystemClipboard {
+    fn open() -> Result<ClipboardContext, ClipboardError> {
+        ClipboardProvider::new().map_err(|e| e.to_string().into())
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn get(&self) -> Result<String, ClipboardError> {
+        Self::open()?
+            .get_contents()
+            .map_err(|e| e.to_string().into())
+    }
+
+    fn set(&self, contents: String) -> Result<(), ClipboardError> {
+        Self::open()?
+            .set_contents(contents)
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryClipboard {
+    contents: Mutex<Option<String>>,
+}
+
+impl Clipboard for InMemoryClipboard {
+    fn get(&self) -> Result<String, ClipboardError> {
+        self.contents
+            .lock()
+            .expect("Clipboard lock shouldn't be poisoned")
+            .clone()
+            .ok_or_else(|| "nothing has been copied yet".into())
+    }
+
+    fn set(&self, contents: String) -> Result<(), ClipboardError> {
+        *self
+            .contents
+            .lock()
+            .expect("Clipboard lock shouldn't be poisoned") = Some(contents);
+        Ok(())
+    }
+}
+
+/// Keeps its own copy of everything copied, so pasting inside the game still
+/// works when `primary` doesn't. Copying still reports `primary`'s error,
+/// since nothing reached other programs.
+pub struct FallbackClipboard<C: Clipboard> {
+    primary: C,
+    local: InMemoryClipboard,
+}
+
+impl<C: Clipboard> FallbackClipboard<C> {
+    pub fn new(primary: C) -> Self {
+        FallbackClipboard {
+            primary,
+            local: InMemoryClipboard::default(),
+        }
+    }
+}
+
+impl<C: Clipboard> Clipboard for FallbackClipboard<C> {
+    fn get(&self) -> Result<String, ClipboardError> {
+        self.primary.get().or_else(|_| self.local.get())
+    }
+
+    fn set(&self, contents: String) -> Result<(), ClipboardError> {
+        self.local.set(contents.clone())?;
+        self.primary.set(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::FailingClipboard;
+
+    #[test]
+    fn the_in_memory_clipboard_pastes_what_was_copied() {
+        let clipboard = InMemoryClipboard::default();
+        assert!(clipboard.get().is_err());
+        clipboard
+            .set("room-1".to_string())
+            .expect("Copying should work");
+        clipboard
+            .set("room-2".to_string())
+            .expect("Copying should work");
+        assert_eq!(clipboard.get().expect("Pasting should work"), "room-2");
+    }
+
+    #[test]
+    fn a_failing_clipboard_still_pastes_inside_the_game() {
+        let clipboard = FallbackClipboard::new(FailingClipboard);
+        assert!(clipboard.get().is_err());
+
+        // nothing reached other programs, so copying reports the failure
+        assert!(clipboard.set("room-1".to_string()).is_err());
+        assert_eq!(clipboard.get().expect("Pasting should work"), "room-1");
+    }
+
+    #[test]
+    fn a_working_clipboard_is_preferred() {
+        let primary = InMemoryClipboard::default();
+        primary
+            .set("from another program".to_string())
+            .expect("Copying should work");
+        let clipboard = FallbackClipboard::new(primary);
+        assert_eq!(
+            clipboard.get().expect("Pasting should work"),
+            "from another program"
+        );
+
+        clipboard
+            .set("room-1".to_string())
+            .expect("Copying should work");
+        assert_eq!(clipboard.get().expect("Pasting should work"), "room-1");
+    }
+}
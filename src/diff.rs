@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{components::UserId, events::GameEvent, serialization::GameState};
+
+// how close to a player a bullet has to disappear to count as a hit
+pub const HIT_RADIUS: f32 = 40.;
+// how close to a player a new bullet has to appear to count as theirs
+const SHOOTER_RADIUS: f32 = 100.;
+
+/// The players and bullets in the last state received, which the next one is
+/// compared against to find out what happened in between.
+#[derive(Default)]
+pub struct PreviousState {
+    received: bool,
+    players: HashMap<UserId, Vec2>,
+    bullets: HashMap<i32, (Vec2, Option<UserId>)>,
+}
+
+impl PreviousState {
+    /// Everything that changed since the last state, which `state` then
+    /// replaces. Events come in the order they should be handled: arrivals
+    /// first, then bullets expiring, and last who left and why. Within each
+    /// group they're in the state's order, or by ID for what's gone from it.
+    pub fn diff(&mut self, state: &GameState) -> Vec<GameEvent> {
+        let mut events = vec![];
+        let initial = !self.received;
+        self.received = true;
+
+        let players: HashMap<UserId, Vec2> = state
+            .players
+            .iter()
+            .map(|player| {
+                (
+                    UserId(player.id.clone()),
+                    Vec2::new(player.position.x, -player.position.y),
+                )
+            })
+            .collect();
+
+        for player in &state.players {
+            let user_id = UserId(player.id.clone());
+            if !self.players.contains_key(&user_id) {
+                events.push(GameEvent::PlayerJoined {
+                    position: players[&user_id],
+                    user_id,
+                    aim_angle: player.aimAngle,
+                    initial,
+                });
+            }
+        }
+
+        let mut bullets = HashMap::new();
+        for bullet in &state.bullets {
+            let position = Vec2::new(bullet.position.x, -bullet.position.y);
+            let owner = match self.bullets.get(&bullet.id) {
+                Some((_, owner)) => owner.clone(),
+                None => {
+                    let reported_owner = bullet.ownerId.as_ref().map(|id| UserId(id.clone()));
+                    // the state doesn't say who fired, so guess whoever is closest
+                    let owner = reported_owner.clone().or_else(|| {
+                        players
+                            .iter()
+                            .map(|(user_id, player)| (user_id, player.distance(position)))
+                            .filter(|(_, distance)| *distance < SHOOTER_RADIUS)
+                            .min_by(|(a_id, a), (b_id, b)| {
+                                a.total_cmp(b).then_with(|| a_id.0.cmp(&b_id.0))
+                            })
+                            .map(|(user_id, _)| user_id.clone())
+                    });
+                    events.push(GameEvent::BulletFired {
+                        bullet_id: bullet.id,
+                        position,
+                        owner: owner.clone(),
+                        owner_reported: reported_owner.is_some(),
+                    });
+                    owner
+                }
+            };
+            bullets.insert(bullet.id, (position, owner));
+        }
+
+        let mut expired_bullets: Vec<_> = self
+            .bullets
+            .iter()
+            .filter(|(bullet_id, _)| !bullets.contains_key(bullet_id))
+            .collect();
+        expired_bullets.sort_by_key(|(bullet_id, _)| **bullet_id);
+        for (bullet_id, (position, _)) in &expired_bullets {
+            events.push(GameEvent::BulletExpired {
+                bullet_id: **bullet_id,
+                position: *position,
+            });
+        }
+
+        let mut left_players: Vec<_> = self
+            .players
+            .iter()
+            .filter(|(user_id, _)| !players.contains_key(user_id))
+            .collect();
+        left_players.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        for (user_id, last_position) in left_players {
+            events.push(GameEvent::PlayerLeft {
+                user_id: user_id.clone(),
+            });
+
+            // a player dropping out right as a bullet vanishes next to them
+            // was most likely shot, by the closest one if there were several
+            let hit_by = expired_bullets
+                .iter()
+                .map(|(_, (position, owner))| (position.distance(*last_position), owner))
+                .filter(|(distance, _)| *distance < HIT_RADIUS)
+                .min_by(|(a, _), (b, _)| a.total_cmp(b));
+            if let Some((_, shooter)) = hit_by {
+                events.push(GameEvent::PlayerEliminated {
+                    victim: user_id.clone(),
+                    shooter: shooter.clone(),
+                });
+            }
+        }
+
+        self.players = players;
+        self.bullets = bullets;
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(json: serde_json::Value) -> GameState {
+        serde_json::from_value(json).expect("Test states should deserialize")
+    }
+
+    fn player(id: &str, x: f32, y: f32) -> serde_json::Value {
+        serde_json::json!({"id": id, "position": {"x": x, "y": y}, "aimAngle": 0.0})
+    }
+
+    fn bullet(id: i32, x: f32, y: f32) -> serde_json::Value {
+        serde_json::json!({"id": id, "position": {"x": x, "y": y}})
+    }
+
+    fn user(id: &str) -> UserId {
+        UserId(id.to_string())
+    }
+
+    #[test]
+    fn players_in_the_first_state_were_already_there() {
+        let mut previous = PreviousState::default();
+        let events = previous.diff(&state(serde_json::json!({
+            "players": [player("a", 10., 20.)],
+            "bullets": [],
+        })));
+        assert!(matches!(
+            &events[..],
+            [GameEvent::PlayerJoined { user_id, position, initial: true, .. }]
+                if *user_id == user("a") && *position == Vec2::new(10., -20.)
+        ));
+
+        let events = previous.diff(&state(serde_json::json!({
+            "players": [player("a", 10., 20.), player("b", 300., 300.)],
+            "bullets": [],
+        })));
+        assert!(matches!(
+            &events[..],
+            [GameEvent::PlayerJoined { user_id, initial: false, .. }] if *user_id == user("b")
+        ));
+    }
+
+    #[test]
+    fn players_leave_in_id_order() {
+        let mut previous = PreviousState::default();
+        previous.diff(&state(serde_json::json!({
+            "players": [player("c", 0., 0.), player("a", 500., 0.), player("b", 900., 0.)],
+            "bullets": [],
+        })));
+        let events = previous.diff(&state(serde_json::json!({
+            "players": [],
+            "bullets": [],
+        })));
+        let left: Vec<&str> = events
+            .iter()
+            .map(|event| match event {
+                GameEvent::PlayerLeft { user_id } => user_id.0.as_str(),
+                other => panic!("Expected only departures, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(left, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn new_bullets_belong_to_the_closest_player() {
+        let mut previous = PreviousState::default();
+        previous.diff(&state(serde_json::json!({
+            "players": [player("near", 0., 0.), player("far", 60., 0.)],
+            "bullets": [],
+        })));
+        let events = previous.diff(&state(serde_json::json!({
+            "players": [player("near", 0., 0.), player("far", 60., 0.)],
+            "bullets": [bullet(1, 20., 0.), bullet(2, 1000., 0.)],
+        })));
+        assert!(matches!(
+            &events[..],
+            [
+                GameEvent::BulletFired { bullet_id: 1, owner: Some(owner), owner_reported: false, .. },
+                GameEvent::BulletFired { bullet_id: 2, owner: None, .. },
+            ] if *owner == user("near")
+        ));
+    }
+
+    #[test]
+    fn bullets_expire_in_id_order() {
+        let mut previous = PreviousState::default();
+        previous.diff(&state(serde_json::json!({
+            "players": [],
+            "bullets": [bullet(7, 0., 0.), bullet(3, 100., 0.), bullet(5, 200., 50.)],
+        })));
+        let events = previous.diff(&state(serde_json::json!({
+            "players": [],
+            "bullets": [bullet(5, 210., 50.)],
+        })));
+        assert!(matches!(
+            &events[..],
+            [
+                GameEvent::BulletExpired { bullet_id: 3, .. },
+                GameEvent::BulletExpired { bullet_id: 7, position },
+            ] if *position == Vec2::ZERO
+        ));
+    }
+
+    #[test]
+    fn the_closest_of_several_bullets_gets_the_kill() {
+        let mut previous = PreviousState::default();
+        let players = [
+            player("victim", 500., 500.),
+            player("near_shooter", 100., 500.),
+            player("far_shooter", 900., 500.),
+        ];
+        previous.diff(&state(serde_json::json!({
+            "players": players,
+            "bullets": [],
+        })));
+        // both bullets are guessed to belong to whoever was next to them
+        previous.diff(&state(serde_json::json!({
+            "players": players,
+            "bullets": [bullet(1, 170., 500.), bullet(2, 830., 500.)],
+        })));
+        previous.diff(&state(serde_json::json!({
+            "players": players,
+            "bullets": [bullet(1, 465., 500.), bullet(2, 510., 500.)],
+        })));
+
+        let events = previous.diff(&state(serde_json::json!({
+            "players": [player("near_shooter", 100., 500.), player("far_shooter", 900., 500.)],
+            "bullets": [],
+        })));
+        let eliminations: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::PlayerEliminated { victim, shooter } => Some((victim, shooter)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            eliminations,
+            vec![(&user("victim"), &Some(user("far_shooter")))]
+        );
+    }
+}
@@ -0,0 +1,591 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{input::mouse::MouseMotion, prelude::*};
+
+use crate::{
+    camera::CameraZoom,
+    components::MainCamera,
+    layers,
+    loading::GameAssets,
+    serialization::{merge_tiles, MapAsset, TileLayer},
+    systems::cursor_world_position,
+};
+
+pub struct EditorPath(pub PathBuf);
+
+/// The map being edited, with walls exploded into individual tiles so that
+/// painting and erasing are simple set operations.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EditorMap {
+    pub tile_size: i32,
+    pub top: i32,
+    pub left: i32,
+    pub bottom: i32,
+    pub right: i32,
+    pub tiles: HashSet<(i32, i32)>,
+    /// Floor and decoration layers aren't editable yet, but are written back untouched
+    pub floor: Option<TileLayer>,
+    pub decorations: Vec<TileLayer>,
+}
+
+/// An empty map the size of the bundled one.
+impl Default for EditorMap {
+    fn default() -> Self {
+        EditorMap {
+            tile_size: 64,
+            top: 0,
+            left: 0,
+            bottom: 33,
+            right: 17,
+            tiles: HashSet::new(),
+            floor: None,
+            decorations: vec![],
+        }
+    }
+}
+
+impl EditorMap {
+    /// Reads the map at `path`, or starts a new one if there's nothing there.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(EditorMap::default());
+        }
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let asset: MapAsset = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+        Ok(EditorMap::from_asset(&asset))
+    }
+
+    pub fn from_asset(map: &MapAsset) -> Self {
+        EditorMap {
+            tile_size: map.tileSize,
+            top: map.top,
+            left: map.left,
+            bottom: map.bottom,
+            right: map.right,
+            tiles: map.wall_tiles(),
+            floor: map.floor.clone(),
+            decorations: map.decorations.clone(),
+        }
+    }
+
+    /// Builds the asset that gets written to disk. Tiles left outside the
+    /// bounds after resizing are dropped.
+    pub fn to_asset(&self) -> MapAsset {
+        let tiles = self
+            .tiles
+            .iter()
+            .copied()
+            .filter(|&tile| self.in_bounds(tile))
+            .collect();
+
+        MapAsset {
+            tileSize: self.tile_size,
+            top: self.top,
+            left: self.left,
+            bottom: self.bottom,
+            right: self.right,
+            walls: merge_tiles(&tiles),
+            floor: self.floor.clone(),
+            decorations: self.decorations.clone(),
+        }
+    }
+
+    pub fn in_bounds(&self, (x, y): (i32, i32)) -> bool {
+        x >= self.left && x < self.right && y >= self.top && y < self.bottom
+    }
+
+    pub fn tile_at(&self, world_position: Vec2) -> (i32, i32) {
+        let tile_size = self.tile_size as f32;
+        (
+            (world_position.x / tile_size).floor() as i32,
+            (-world_position.y / tile_size).floor() as i32,
+        )
+    }
+
+    pub fn tile_center(&self, (x, y): (i32, i32)) -> Vec2 {
+        let tile_size = self.tile_size as f32;
+        Vec2::new(tile_size * (x as f32 + 0.5), -tile_size * (y as f32 + 0.5))
+    }
+}
+
+#[derive(Default)]
+pub struct EditHistory {
+    undo: Vec<EditorMap>,
+    redo: Vec<EditorMap>,
+}
+
+impl EditHistory {
+    /// Remembers `map` as the state to return to on the next undo.
+    pub fn record(&mut self, map: &EditorMap) {
+        self.undo.push(map.clone());
+        self.redo.clear();
+    }
+
+    /// Returns the map to restore, keeping `current` around for redo.
+    pub fn undo(&mut self, current: &EditorMap) -> Option<EditorMap> {
+        let previous = self.undo.pop()?;
+        self.redo.push(current.clone());
+        Some(previous)
+    }
+
+    pub fn redo(&mut self, current: &EditorMap) -> Option<EditorMap> {
+        let next = self.redo.pop()?;
+        self.undo.push(current.clone());
+        Some(next)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StrokeMode {
+    Paint,
+    Erase,
+}
+
+/// A click-and-drag in progress. The whole stroke is a single undo step.
+#[derive(Default)]
+pub struct EditorStroke {
+    mode: Option<StrokeMode>,
+    changed: bool,
+}
+
+pub struct ShowGrid(pub bool);
+
+#[derive(Component)]
+pub struct EditorTile;
+
+#[derive(Component)]
+pub struct GridLine;
+
+#[derive(Component)]
+pub struct EditorStatus;
+
+const GRID_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const BOUNDS_COLOR: Color = Color::rgb(0.95, 0.75, 0.2);
+const CAMERA_PAN_SPEED: f32 = 600.;
+const HELP_TEXT: &str = "LMB paint | RMB erase | WASD/MMB pan | wheel zoom | Shift+arrows: bottom-right bound | Alt+arrows: top-left bound | G grid | Ctrl+Z/Y undo/redo | Ctrl+S save";
+
+/// Sets up editing the map opened before the app started.
+pub fn load_editor_map(path: Res<EditorPath>, map: Res<EditorMap>, mut commands: Commands) {
+    if !path.0.exists() {
+        info!("{} does not exist, starting a new map", path.0.display());
+    }
+
+    let center = (map.tile_center((map.left, map.top))
+        + map.tile_center((map.right - 1, map.bottom - 1)))
+        / 2.;
+    commands
+        .spawn_bundle(Camera2dBundle {
+            transform: Transform::from_xyz(center.x, center.y, 999.9),
+            ..default()
+        })
+        .insert(CameraZoom {
+            scale: 1.,
+            min_scale: 0.25,
+            max_scale: 8.,
+        })
+        .insert(MainCamera);
+
+    commands.insert_resource(EditHistory::default());
+    commands.insert_resource(EditorStroke::default());
+    commands.insert_resource(ShowGrid(true));
+}
+
+pub fn display_editor_help(assets: Res<GameAssets>, mut commands: Commands) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                HELP_TEXT,
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 14.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(5.0),
+                    bottom: Val::Px(5.0),
+                    ..default()
+                },
+                max_size: Size::new(Val::Px(790.0), Val::Undefined),
+                ..default()
+            }),
+        )
+        .insert(EditorStatus);
+}
+
+pub fn editor_camera_controls(
+    keyboard: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    mut camera_query: Query<(&mut Transform, &OrthographicProjection), With<MainCamera>>,
+    time: Res<Time>,
+) {
+    let (mut camera_transform, projection) = camera_query.single_mut();
+
+    let mut direction = Vec2::ZERO;
+    if keyboard.pressed(KeyCode::W) {
+        direction.y += 1.;
+    }
+    // S doubles as save while Ctrl is held
+    if keyboard.pressed(KeyCode::S) && !ctrl_pressed(&keyboard) {
+        direction.y -= 1.;
+    }
+    if keyboard.pressed(KeyCode::A) {
+        direction.x -= 1.;
+    }
+    if keyboard.pressed(KeyCode::D) {
+        direction.x += 1.;
+    }
+    let pan = direction * CAMERA_PAN_SPEED * projection.scale * time.delta_seconds();
+
+    let mut drag = Vec2::ZERO;
+    for event in mouse_motion_events.iter() {
+        if mouse_buttons.pressed(MouseButton::Middle) {
+            // screen y grows downwards, world y grows upwards
+            drag += Vec2::new(-event.delta.x, event.delta.y) * projection.scale;
+        }
+    }
+
+    camera_transform.translation += (pan + drag).extend(0.);
+}
+
+pub fn paint_tiles(
+    mouse_buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut map: ResMut<EditorMap>,
+    mut history: ResMut<EditHistory>,
+    mut stroke: ResMut<EditorStroke>,
+) {
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        *stroke = EditorStroke {
+            mode: Some(StrokeMode::Paint),
+            changed: false,
+        };
+    } else if mouse_buttons.just_pressed(MouseButton::Right) {
+        *stroke = EditorStroke {
+            mode: Some(StrokeMode::Erase),
+            changed: false,
+        };
+    }
+
+    let mode = match stroke.mode {
+        Some(mode) => mode,
+        None => return,
+    };
+
+    let button = match mode {
+        StrokeMode::Paint => MouseButton::Left,
+        StrokeMode::Erase => MouseButton::Right,
+    };
+    if !mouse_buttons.pressed(button) {
+        *stroke = EditorStroke::default();
+        return;
+    }
+
+    let (camera, camera_transform) = camera_query.single();
+    let tile = match cursor_world_position(&windows, camera, camera_transform) {
+        Some(position) => map.tile_at(position),
+        None => return,
+    };
+
+    if !map.in_bounds(tile) {
+        return;
+    }
+
+    let needs_change = match mode {
+        StrokeMode::Paint => !map.tiles.contains(&tile),
+        StrokeMode::Erase => map.tiles.contains(&tile),
+    };
+    if !needs_change {
+        return;
+    }
+
+    // the undo step is taken on the first change so empty clicks don't leave one behind
+    if !stroke.changed {
+        history.record(&map);
+        stroke.changed = true;
+    }
+    match mode {
+        StrokeMode::Paint => map.tiles.insert(tile),
+        StrokeMode::Erase => map.tiles.remove(&tile),
+    };
+}
+
+pub fn edit_bounds(
+    keyboard: Res<Input<KeyCode>>,
+    mut map: ResMut<EditorMap>,
+    mut history: ResMut<EditHistory>,
+) {
+    let shift = keyboard.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    let alt = keyboard.any_pressed([KeyCode::LAlt, KeyCode::RAlt]);
+    if !shift && !alt {
+        return;
+    }
+
+    let mut delta = IVec2::ZERO;
+    if keyboard.just_pressed(KeyCode::Left) {
+        delta.x -= 1;
+    }
+    if keyboard.just_pressed(KeyCode::Right) {
+        delta.x += 1;
+    }
+    if keyboard.just_pressed(KeyCode::Up) {
+        delta.y -= 1;
+    }
+    if keyboard.just_pressed(KeyCode::Down) {
+        delta.y += 1;
+    }
+    if delta == IVec2::ZERO {
+        return;
+    }
+
+    let mut resized = map.clone();
+    if shift {
+        resized.right += delta.x;
+        resized.bottom += delta.y;
+    } else {
+        resized.left += delta.x;
+        resized.top += delta.y;
+    }
+
+    if resized.right <= resized.left || resized.bottom <= resized.top {
+        return;
+    }
+
+    history.record(&map);
+    *map = resized;
+}
+
+pub fn editor_shortcuts(
+    keyboard: Res<Input<KeyCode>>,
+    path: Res<EditorPath>,
+    stroke: Res<EditorStroke>,
+    mut map: ResMut<EditorMap>,
+    mut history: ResMut<EditHistory>,
+    mut show_grid: ResMut<ShowGrid>,
+    mut status_query: Query<&mut Text, With<EditorStatus>>,
+) {
+    if keyboard.just_pressed(KeyCode::G) {
+        show_grid.0 = !show_grid.0;
+    }
+
+    if !ctrl_pressed(&keyboard) || stroke.mode.is_some() {
+        return;
+    }
+
+    let shift = keyboard.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    let status =
+        if keyboard.just_pressed(KeyCode::Y) || (shift && keyboard.just_pressed(KeyCode::Z)) {
+            match history.redo(&map) {
+                Some(next) => *map = next,
+                None => return,
+            }
+            "Redo".to_string()
+        } else if keyboard.just_pressed(KeyCode::Z) {
+            match history.undo(&map) {
+                Some(previous) => *map = previous,
+                None => return,
+            }
+            "Undo".to_string()
+        } else if keyboard.just_pressed(KeyCode::S) {
+            save_map(&map, &path.0)
+        } else {
+            return;
+        };
+
+    for mut text in &mut status_query {
+        text.sections[0].value = format!("{} | {}", status, HELP_TEXT);
+    }
+}
+
+fn save_map(map: &EditorMap, path: &Path) -> String {
+    let asset = map.to_asset();
+    let json = serde_json::to_string_pretty(&asset).expect("Serialization should work");
+    match fs::write(path, json) {
+        Ok(()) => {
+            info!("Saved {} walls to {}", asset.walls.len(), path.display());
+            format!("Saved {}", path.display())
+        }
+        Err(e) => {
+            error!("Failed to save map to {}. Error was {}", path.display(), e);
+            format!("Save failed: {}", e)
+        }
+    }
+}
+
+fn ctrl_pressed(keyboard: &Input<KeyCode>) -> bool {
+    keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl])
+}
+
+pub fn draw_editor_tiles(
+    assets: Res<GameAssets>,
+    map: Res<EditorMap>,
+    tile_query: Query<Entity, With<EditorTile>>,
+    mut commands: Commands,
+) {
+    if !map.is_changed() {
+        return;
+    }
+
+    for entity in &tile_query {
+        commands.entity(entity).despawn();
+    }
+
+    for &tile in &map.tiles {
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: assets.wall.clone(),
+                transform: Transform::from_translation(map.tile_center(tile).extend(layers::WALL)),
+                sprite: Sprite {
+                    // dim tiles that fall outside the bounds and won't be saved
+                    color: if map.in_bounds(tile) {
+                        Color::WHITE
+                    } else {
+                        Color::rgba(1.0, 1.0, 1.0, 0.3)
+                    },
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(EditorTile);
+    }
+}
+
+pub fn draw_grid(
+    map: Res<EditorMap>,
+    show_grid: Res<ShowGrid>,
+    line_query: Query<Entity, With<GridLine>>,
+    mut commands: Commands,
+) {
+    if !map.is_changed() && !show_grid.is_changed() {
+        return;
+    }
+
+    for entity in &line_query {
+        commands.entity(entity).despawn();
+    }
+
+    let tile_size = map.tile_size as f32;
+    let min = Vec2::new(map.left as f32, -map.bottom as f32) * tile_size;
+    let max = Vec2::new(map.right as f32, -map.top as f32) * tile_size;
+    let size = max - min;
+
+    let mut spawn_line = |center: Vec2, size: Vec2, color: Color| {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(size),
+                    ..default()
+                },
+                transform: Transform::from_translation(center.extend(layers::OVERLAY)),
+                ..default()
+            })
+            .insert(GridLine);
+    };
+
+    if show_grid.0 {
+        for x in map.left + 1..map.right {
+            let x = x as f32 * tile_size;
+            spawn_line(
+                Vec2::new(x, min.y + size.y / 2.),
+                Vec2::new(1., size.y),
+                GRID_COLOR,
+            );
+        }
+        for y in map.top + 1..map.bottom {
+            let y = -y as f32 * tile_size;
+            spawn_line(
+                Vec2::new(min.x + size.x / 2., y),
+                Vec2::new(size.x, 1.),
+                GRID_COLOR,
+            );
+        }
+    }
+
+    // the bounds are always drawn so resizing is visible with the grid hidden
+    for x in [min.x, max.x] {
+        spawn_line(
+            Vec2::new(x, min.y + size.y / 2.),
+            Vec2::new(3., size.y + 3.),
+            BOUNDS_COLOR,
+        );
+    }
+    for y in [min.y, max.y] {
+        spawn_line(
+            Vec2::new(min.x + size.x / 2., y),
+            Vec2::new(size.x + 3., 3.),
+            BOUNDS_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_with(tiles: &[(i32, i32)]) -> EditorMap {
+        EditorMap {
+            tiles: tiles.iter().copied().collect(),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_step_through_recorded_maps() {
+        let mut history = EditHistory::default();
+        let first = map_with(&[]);
+        let second = map_with(&[(1, 1)]);
+        let third = map_with(&[(1, 1), (2, 1)]);
+
+        assert_eq!(history.undo(&first), None);
+        history.record(&first);
+        history.record(&second);
+
+        assert_eq!(history.undo(&third), Some(second.clone()));
+        assert_eq!(history.undo(&second), Some(first.clone()));
+        assert_eq!(history.undo(&first), None);
+
+        assert_eq!(history.redo(&first), Some(second.clone()));
+        assert_eq!(history.redo(&second), Some(third.clone()));
+        assert_eq!(history.redo(&third), None);
+    }
+
+    #[test]
+    fn recording_after_undo_drops_redo() {
+        let mut history = EditHistory::default();
+        let first = map_with(&[]);
+        let second = map_with(&[(1, 1)]);
+
+        history.record(&first);
+        assert_eq!(history.undo(&second), Some(first.clone()));
+        history.record(&first);
+        assert_eq!(history.redo(&first), None);
+    }
+
+    #[test]
+    fn saving_drops_tiles_outside_the_bounds() {
+        let mut map = map_with(&[(0, 0), (1, 0), (16, 32), (17, 0), (-1, 5)]);
+        map.right = 17;
+        let saved = EditorMap::from_asset(&map.to_asset());
+        assert_eq!(saved.tiles, [(0, 0), (1, 0), (16, 32)].into());
+    }
+
+    #[test]
+    fn opening_a_malformed_map_is_an_error() {
+        let path = std::env::temp_dir().join("topdown-shooter-malformed-map.json");
+        fs::write(&path, "{ not a map").expect("Writing a temporary file should work");
+        assert!(EditorMap::open(&path).is_err());
+        fs::remove_file(&path).ok();
+
+        let missing = std::env::temp_dir().join("topdown-shooter-missing-map.json");
+        assert_eq!(EditorMap::open(&missing), Ok(EditorMap::default()));
+    }
+}
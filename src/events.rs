@@ -0,0 +1,60 @@
+use bevy::prelude::Vec2;
+
+use crate::{components::UserId, scoreboard::PlayerStats};
+
+/// Things that happen to the local player, for feedback like screen shake.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LocalPlayerEvent {
+    /// A shot was sent to the server
+    Fired,
+    /// A bullet disappeared right next to the local player
+    Hit,
+    /// The local player dropped out of the game state
+    Removed,
+}
+
+/// What changed between two consecutive game states. Positions are in world
+/// coordinates.
+#[derive(Clone, Debug)]
+pub enum GameEvent {
+    PlayerJoined {
+        user_id: UserId,
+        position: Vec2,
+        aim_angle: f32,
+        /// The player was already there when the room was joined
+        initial: bool,
+    },
+    PlayerLeft {
+        user_id: UserId,
+    },
+    BulletFired {
+        bullet_id: i32,
+        position: Vec2,
+        owner: Option<UserId>,
+        /// The server said who fired it, rather than it being guessed
+        owner_reported: bool,
+    },
+    BulletExpired {
+        bullet_id: i32,
+        position: Vec2,
+    },
+    /// A player dropped out of the state right as a bullet vanished next to them
+    PlayerEliminated {
+        victim: UserId,
+        shooter: Option<UserId>,
+    },
+}
+
+/// A player's stats as sent by the server, which replace anything derived.
+#[derive(Clone, Debug)]
+pub struct StatsReported {
+    pub user_id: UserId,
+    pub stats: PlayerStats,
+}
+
+/// A chat message someone else in the room sent.
+#[derive(Clone, Debug)]
+pub struct ChatReceived {
+    pub sender: UserId,
+    pub message: String,
+}
@@ -0,0 +1,173 @@
+//! Stand-ins for Hathora and the desktop, so connecting, talking to a room and
+//! copying can be tested without a network or a display.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use hathora_client_sdk::HathoraTransport;
+
+use crate::{
+    connection::{BackendError, ConnectStage, GameBackend},
+    copy_paste::{Clipboard, ClipboardError},
+};
+
+/// A well-formed token for `FAKE_USER_ID`: a JWT whose payload is `{"id":"fake-user"}`.
+pub const FAKE_TOKEN: &str = "e30.eyJpZCI6ImZha2UtdXNlciJ9.c2ln";
+pub const FAKE_USER_ID: &str = "fake-user";
+pub const FAKE_ROOM_ID: &str = "fake-room";
+
+/// A backend where each call can be made to fail, and which remembers the
+/// calls made to it along with the connection stage at the time.
+#[derive(Default)]
+pub struct FakeBackend {
+    pub fail_nickname_login: bool,
+    pub fail_login: bool,
+    pub fail_create_room: bool,
+    /// Hand out a token with no payload
+    pub bad_token: bool,
+    pub fail_connect: bool,
+    /// The stage `log_in_and_connect` reports to, if calls should note it
+    pub stage: Option<Arc<Mutex<ConnectStage>>>,
+    pub calls: Mutex<Vec<(&'static str, Option<ConnectStage>)>>,
+    pub transport: FakeTransport,
+}
+
+impl FakeBackend {
+    fn call(&self, name: &'static str, fail: bool) -> Result<(), BackendError> {
+        let stage = self.stage.as_ref().map(|stage| {
+            *stage
+                .lock()
+                .expect("Connection stage lock shouldn't be poisoned")
+        });
+        self.calls
+            .lock()
+            .expect("Call list lock shouldn't be poisoned")
+            .push((name, stage));
+        if fail {
+            Err(format!("{} failed on purpose", name).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn call_names(&self) -> Vec<&'static str> {
+        self.calls
+            .lock()
+            .expect("Call list lock shouldn't be poisoned")
+            .iter()
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    fn token(&self) -> String {
+        if self.bad_token {
+            "not-a-jwt".to_string()
+        } else {
+            FAKE_TOKEN.to_string()
+        }
+    }
+}
+
+impl GameBackend for FakeBackend {
+    fn log_in(&self) -> Result<String, BackendError> {
+        self.call("log_in", self.fail_login)?;
+        Ok(self.token())
+    }
+
+    fn log_in_with_nickname(&self, _nickname: &str) -> Result<String, BackendError> {
+        self.call("log_in_with_nickname", self.fail_nickname_login)?;
+        Ok(self.token())
+    }
+
+    fn create_room(&self, _token: &str) -> Result<String, BackendError> {
+        self.call("create_room", self.fail_create_room)?;
+        Ok(FAKE_ROOM_ID.to_string())
+    }
+
+    fn user_id(&self, token: &str) -> Result<String, BackendError> {
+        self.call("user_id", token != FAKE_TOKEN)?;
+        Ok(FAKE_USER_ID.to_string())
+    }
+
+    fn connect(
+        &self,
+        _token: &str,
+        _room_id: &str,
+    ) -> Result<Box<dyn HathoraTransport>, BackendError> {
+        self.call("connect", self.fail_connect)?;
+        Ok(Box::new(self.transport.clone()))
+    }
+}
+
+/// A transport that keeps what's written and reads from a queue. Clones share
+/// both, so a test can keep one while the game owns another.
+#[derive(Clone, Default)]
+pub struct FakeTransport {
+    pub written: Arc<Mutex<Vec<Vec<u8>>>>,
+    pub incoming: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl FakeTransport {
+    /// Queues a message as if the server had sent it.
+    pub fn receive(&self, message: &str) {
+        self.incoming
+            .lock()
+            .expect("Incoming lock shouldn't be poisoned")
+            .push_back(message.as_bytes().to_vec());
+    }
+
+    pub fn written_messages(&self) -> Vec<String> {
+        self.written
+            .lock()
+            .expect("Written lock shouldn't be poisoned")
+            .iter()
+            .map(|message| String::from_utf8_lossy(message).into_owned())
+            .collect()
+    }
+}
+
+impl HathoraTransport for FakeTransport {
+    fn connect(&mut self, _state_id: &str, _token: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn write_message(&mut self, data: Vec<u8>) -> anyhow::Result<()> {
+        self.written
+            .lock()
+            .expect("Written lock shouldn't be poisoned")
+            .push(data);
+        Ok(())
+    }
+
+    /// Like the web socket, fails when there's nothing to read.
+    fn read_message(&mut self) -> anyhow::Result<Vec<u8>> {
+        self.incoming
+            .lock()
+            .expect("Incoming lock shouldn't be poisoned")
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("nothing to read"))
+    }
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    fn disconnect(&mut self, _code: Option<i32>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A clipboard that can't be opened, like on a headless machine.
+pub struct FailingClipboard;
+
+impl Clipboard for FailingClipboard {
+    fn get(&self) -> Result<String, ClipboardError> {
+        Err("no clipboard here".into())
+    }
+
+    fn set(&self, _contents: String) -> Result<(), ClipboardError> {
+        Err("no clipboard here".into())
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{
+    components::{BulletId, CurrentPlayer, UserId},
+    layers,
+    loading::GameAssets,
+    minimap::MinimapMarker,
+    serialization::MapAsset,
+    spectator::Spectator,
+};
+
+const FOG_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.75);
+/// How far to either side of a tile edge to look, in tiles, for sight lines
+/// running exactly along it.
+const EDGE_NUDGE: f32 = 0.001;
+
+pub struct FogOfWarSettings {
+    pub enabled: bool,
+}
+
+/// The map's walls in tile space, for answering what can be seen from where.
+/// Positions passed in and out are world coordinates.
+pub struct Occluders {
+    tile_size: f32,
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+    walls: HashSet<(i32, i32)>,
+}
+
+impl Occluders {
+    pub fn from_map(map: &MapAsset) -> Self {
+        Occluders {
+            tile_size: map.tileSize as f32,
+            left: map.left,
+            top: map.top,
+            right: map.right,
+            bottom: map.bottom,
+            walls: map.wall_tiles(),
+        }
+    }
+
+    fn to_tile_space(&self, world_position: Vec2) -> Vec2 {
+        Vec2::new(world_position.x, -world_position.y) / self.tile_size
+    }
+
+    pub fn tile_center(&self, (x, y): (i32, i32)) -> Vec2 {
+        Vec2::new(x as f32 + 0.5, -(y as f32 + 0.5)) * self.tile_size
+    }
+
+    /// Whether nothing but open floor lies between `from` and `to`. The tiles
+    /// containing the two endpoints never block, so the face of a wall counts
+    /// as visible.
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let start = self.to_tile_space(from);
+        let end = self.to_tile_space(to);
+
+        // a line exactly along a tile edge passes between the tiles on either
+        // side, so it's only blocked if both sides are
+        let nudge = if start.x == end.x && start.x.fract() == 0. {
+            Some(Vec2::new(EDGE_NUDGE, 0.))
+        } else if start.y == end.y && start.y.fract() == 0. {
+            Some(Vec2::new(0., EDGE_NUDGE))
+        } else {
+            None
+        };
+        match nudge {
+            Some(nudge) => {
+                self.clear_path(start - nudge, end - nudge)
+                    || self.clear_path(start + nudge, end + nudge)
+            }
+            None => self.clear_path(start, end),
+        }
+    }
+
+    /// `line_of_sight` in tile space.
+    fn clear_path(&self, start: Vec2, end: Vec2) -> bool {
+        let end_tile = (end.x.floor() as i32, end.y.floor() as i32);
+        let mut tile = (start.x.floor() as i32, start.y.floor() as i32);
+
+        // walk every tile the segment crosses (Amanatides & Woo)
+        let direction = end - start;
+        let step = (direction.x.signum() as i32, direction.y.signum() as i32);
+        let boundary = |position: f32, tile: i32, step: i32| {
+            if step > 0 {
+                (tile + 1) as f32 - position
+            } else {
+                position - tile as f32
+            }
+        };
+        let mut t_max = Vec2::new(
+            if direction.x != 0. {
+                boundary(start.x, tile.0, step.0) / direction.x.abs()
+            } else {
+                f32::INFINITY
+            },
+            if direction.y != 0. {
+                boundary(start.y, tile.1, step.1) / direction.y.abs()
+            } else {
+                f32::INFINITY
+            },
+        );
+        let t_delta = Vec2::new(1. / direction.x.abs(), 1. / direction.y.abs());
+
+        let steps = (end_tile.0 - tile.0).abs() + (end_tile.1 - tile.1).abs();
+        for _ in 0..steps {
+            if t_max.x < t_max.y {
+                tile.0 += step.0;
+                t_max.x += t_delta.x;
+            } else {
+                tile.1 += step.1;
+                t_max.y += t_delta.y;
+            }
+
+            if tile == end_tile {
+                return true;
+            }
+            if self.walls.contains(&tile) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Every tile within the map bounds whose center can be seen from `origin`.
+    pub fn visible_tiles(&self, origin: Vec2) -> HashSet<(i32, i32)> {
+        let mut visible = HashSet::new();
+        for x in self.left..self.right {
+            for y in self.top..self.bottom {
+                if self.line_of_sight(origin, self.tile_center((x, y))) {
+                    visible.insert((x, y));
+                }
+            }
+        }
+        visible
+    }
+}
+
+#[derive(Component)]
+pub struct FogTile((i32, i32));
+
+pub fn spawn_fog(
+    settings: Res<FogOfWarSettings>,
+    assets: Res<GameAssets>,
+    map_assets: Res<Assets<MapAsset>>,
+    mut commands: Commands,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let map = match map_assets.get(&assets.map) {
+        Some(map) => map,
+        None => return,
+    };
+
+    let occluders = Occluders::from_map(map);
+    for x in map.left..map.right {
+        for y in map.top..map.bottom {
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: FOG_COLOR,
+                        custom_size: Some(Vec2::splat(occluders.tile_size)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        occluders.tile_center((x, y)).extend(layers::FOG),
+                    ),
+                    ..default()
+                })
+                .insert(FogTile((x, y)));
+        }
+    }
+    commands.insert_resource(occluders);
+}
+
+/// Where the fog is seen from. Spectators see everything.
+fn local_player_position(
+    spectator: &Spectator,
+    current_player_query: &Query<&Transform, With<CurrentPlayer>>,
+) -> Option<Vec2> {
+    if spectator.forced {
+        return None;
+    }
+    current_player_query
+        .iter()
+        .next()
+        .map(|transform| transform.translation.truncate())
+}
+
+pub fn update_fog(
+    occluders: Option<Res<Occluders>>,
+    spectator: Res<Spectator>,
+    current_player_query: Query<&Transform, With<CurrentPlayer>>,
+    mut fog_query: Query<(&FogTile, &mut Visibility)>,
+) {
+    let occluders = match occluders {
+        Some(occluders) => occluders,
+        None => return,
+    };
+
+    // with nobody to see from, e.g. before spawning, the whole map is revealed
+    let visible = local_player_position(&spectator, &current_player_query)
+        .map(|origin| occluders.visible_tiles(origin));
+
+    for (fog_tile, mut visibility) in &mut fog_query {
+        let fogged = match &visible {
+            Some(visible) => !visible.contains(&fog_tile.0),
+            None => false,
+        };
+        if visibility.is_visible != fogged {
+            visibility.is_visible = fogged;
+        }
+    }
+}
+
+pub fn hide_unseen_entities(
+    occluders: Option<Res<Occluders>>,
+    spectator: Res<Spectator>,
+    current_player_query: Query<&Transform, With<CurrentPlayer>>,
+    mut entity_query: Query<
+        (&Transform, &mut Visibility, Option<&Children>),
+        (
+            Or<(With<UserId>, With<BulletId>)>,
+            Without<CurrentPlayer>,
+            Without<MinimapMarker>,
+        ),
+    >,
+    mut marker_query: Query<&mut Visibility, With<MinimapMarker>>,
+) {
+    let occluders = match occluders {
+        Some(occluders) => occluders,
+        None => return,
+    };
+    let origin = local_player_position(&spectator, &current_player_query);
+
+    for (transform, mut visibility, children) in &mut entity_query {
+        let seen = match origin {
+            Some(origin) => occluders.line_of_sight(origin, transform.translation.truncate()),
+            None => true,
+        };
+        if visibility.is_visible != seen {
+            visibility.is_visible = seen;
+        }
+
+        // the minimap mustn't give away what the fog hides
+        if let Some(children) = children {
+            for &child in children.iter() {
+                if let Ok(mut marker_visibility) = marker_query.get_mut(child) {
+                    if marker_visibility.is_visible != seen {
+                        marker_visibility.is_visible = seen;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundled_map() -> Occluders {
+        let map: MapAsset = serde_json::from_str(include_str!("../assets/data/map.json"))
+            .expect("Bundled map should be valid");
+        Occluders::from_map(&map)
+    }
+
+    /// World position of a point given in tiles.
+    fn at(occluders: &Occluders, x: f32, y: f32) -> Vec2 {
+        Vec2::new(x, -y) * occluders.tile_size
+    }
+
+    #[test]
+    fn open_floor_is_visible() {
+        let map = bundled_map();
+        let visible = [
+            // along the open top rows
+            ((0, 0), (16, 0)),
+            ((8, 0), (8, 3)),
+            // out of the open bottom of the top room
+            ((8, 7), (8, 11)),
+            // between the pillars in the middle
+            ((7, 14), (7, 21)),
+        ];
+        for (from, to) in visible {
+            assert!(
+                map.line_of_sight(map.tile_center(from), map.tile_center(to)),
+                "{:?} should see {:?}",
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn walls_hide_what_is_behind_them() {
+        let map = bundled_map();
+        let hidden = [
+            // through the top of the top room
+            ((8, 2), (8, 7)),
+            // through its left side
+            ((2, 7), (8, 7)),
+            // through the bottom of the bottom room
+            ((8, 32), (8, 27)),
+            // through a pillar
+            ((9, 11), (10, 15)),
+        ];
+        for (from, to) in hidden {
+            assert!(
+                !map.line_of_sight(map.tile_center(from), map.tile_center(to)),
+                "{:?} shouldn't see {:?}",
+                from,
+                to
+            );
+            assert!(
+                !map.line_of_sight(map.tile_center(to), map.tile_center(from)),
+                "{:?} shouldn't see {:?}",
+                to,
+                from
+            );
+        }
+    }
+
+    #[test]
+    fn the_face_of_a_wall_is_visible_but_not_past_it() {
+        let map = bundled_map();
+        let visible = map.visible_tiles(map.tile_center((8, 2)));
+        assert!(visible.contains(&(8, 4)));
+        assert!(!visible.contains(&(8, 5)));
+        // looking out from inside a wall isn't blocked by that wall
+        assert!(map.line_of_sight(map.tile_center((8, 4)), map.tile_center((8, 2))));
+    }
+
+    #[test]
+    fn lines_along_a_tile_edge_are_not_blocked() {
+        let map = bundled_map();
+        // down the outside of the top room's left wall
+        assert!(map.line_of_sight(at(&map, 4., 3.5), at(&map, 4., 10.5)));
+        // down the inside of it
+        assert!(map.line_of_sight(at(&map, 5., 5.5), at(&map, 5., 10.5)));
+        // along the top of the top room
+        assert!(map.line_of_sight(at(&map, 2.5, 4.), at(&map, 14.5, 4.)));
+        // between the two halves of a pillar there's nowhere to see through
+        assert!(!map.line_of_sight(at(&map, 10., 12.5), at(&map, 10., 14.5)));
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fmt, str::FromStr};
+
+pub const JOIN_LINK_PREFIX: &str = "topdown-shooter://join/";
+
+const MAX_ID_LENGTH: usize = 64;
+
+/// A room to join, given either as a bare room ID or as a shareable link like
+/// `topdown-shooter://join/<room_id>?app=<app_id>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct JoinTarget {
+    pub room_id: String,
+    /// Set when a link names the app the room belongs to
+    pub app_id: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum JoinTargetError {
+    Empty,
+    TooLong,
+    InvalidCharacter(char),
+    MalformedLink(String),
+}
+
+impl fmt::Display for JoinTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinTargetError::Empty => write!(f, "the room ID is empty"),
+            JoinTargetError::TooLong => {
+                write!(f, "IDs are at most {} characters long", MAX_ID_LENGTH)
+            }
+            JoinTargetError::InvalidCharacter(c) => {
+                write!(f, "IDs can't contain {:?}", c)
+            }
+            JoinTargetError::MalformedLink(reason) => write!(f, "invalid join link: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for JoinTargetError {}
+
+/// Room and app IDs are letters, digits, dashes and underscores.
+pub fn validate_id(id: &str) -> Result<(), JoinTargetError> {
+    if id.is_empty() {
+        return Err(JoinTargetError::Empty);
+    }
+    if id.len() > MAX_ID_LENGTH {
+        return Err(JoinTargetError::TooLong);
+    }
+    match id
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-' && *c != '_')
+    {
+        Some(c) => Err(JoinTargetError::InvalidCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+impl FromStr for JoinTarget {
+    type Err = JoinTargetError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let link = match text.strip_prefix(JOIN_LINK_PREFIX) {
+            Some(link) => link,
+            None => {
+                validate_id(text)?;
+                return Ok(JoinTarget {
+                    room_id: text.to_string(),
+                    app_id: None,
+                });
+            }
+        };
+
+        let (path, query) = link.split_once('?').unwrap_or((link, ""));
+        let room_id = path.trim_end_matches('/');
+        validate_id(room_id)?;
+
+        let mut app_id = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            match pair.split_once('=') {
+                Some(("app", value)) => {
+                    validate_id(value).map_err(|e| {
+                        JoinTargetError::MalformedLink(format!("bad app ID: {}", e))
+                    })?;
+                    app_id = Some(value.to_string());
+                }
+                // leave room for parameters added later
+                Some(_) => {}
+                None => {
+                    return Err(JoinTargetError::MalformedLink(format!(
+                        "expected key=value, got {:?}",
+                        pair
+                    )))
+                }
+            }
+        }
+
+        Ok(JoinTarget {
+            room_id: room_id.to_string(),
+            app_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<JoinTarget, JoinTargetError> {
+        text.parse()
+    }
+
+    #[test]
+    fn a_bare_room_id_is_a_target() {
+        assert_eq!(
+            parse("  2bb2nqdp8c4k0 "),
+            Ok(JoinTarget {
+                room_id: "2bb2nqdp8c4k0".to_string(),
+                app_id: None,
+            })
+        );
+    }
+
+    #[test]
+    fn links_can_name_the_app() {
+        assert_eq!(
+            parse("topdown-shooter://join/room_1?app=app-2"),
+            Ok(JoinTarget {
+                room_id: "room_1".to_string(),
+                app_id: Some("app-2".to_string()),
+            })
+        );
+        // unknown parameters are left for later versions
+        assert_eq!(
+            parse("topdown-shooter://join/room_1?v=2&app=app-2"),
+            parse("topdown-shooter://join/room_1?app=app-2")
+        );
+    }
+
+    #[test]
+    fn links_can_end_in_a_slash() {
+        assert_eq!(
+            parse("topdown-shooter://join/room-1/"),
+            Ok(JoinTarget {
+                room_id: "room-1".to_string(),
+                app_id: None,
+            })
+        );
+        assert_eq!(
+            parse("topdown-shooter://join/room-1/?app=app-2")
+                .expect("Parsing should work")
+                .room_id,
+            "room-1"
+        );
+    }
+
+    #[test]
+    fn bad_app_ids_are_rejected() {
+        assert!(matches!(
+            parse("topdown-shooter://join/room-1?app=no/way"),
+            Err(JoinTargetError::MalformedLink(_))
+        ));
+        assert!(matches!(
+            parse("topdown-shooter://join/room-1?app="),
+            Err(JoinTargetError::MalformedLink(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_queries_are_rejected() {
+        for link in [
+            "topdown-shooter://join/room-1?app",
+            "topdown-shooter://join/room-1?&&oops",
+            "topdown-shooter://join/room-1?app=a?app=b",
+        ] {
+            assert!(
+                matches!(parse(link), Err(JoinTargetError::MalformedLink(_))),
+                "{} should be rejected",
+                link
+            );
+        }
+    }
+
+    #[test]
+    fn ids_must_be_short_and_plain() {
+        assert_eq!(parse(""), Err(JoinTargetError::Empty));
+        assert_eq!(
+            parse("topdown-shooter://join/"),
+            Err(JoinTargetError::Empty)
+        );
+        assert!(validate_id(&"a".repeat(MAX_ID_LENGTH)).is_ok());
+        assert_eq!(
+            parse(&"a".repeat(MAX_ID_LENGTH + 1)),
+            Err(JoinTargetError::TooLong)
+        );
+        assert_eq!(parse("room 1"), Err(JoinTargetError::InvalidCharacter(' ')));
+        assert_eq!(
+            parse("topdown-shooter://join/röom"),
+            Err(JoinTargetError::InvalidCharacter('ö'))
+        );
+        assert_eq!(
+            parse("https://example.com/room"),
+            Err(JoinTargetError::InvalidCharacter(':'))
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::{cmp::Reverse, collections::HashSet, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::{
+    components::{InGameEntity, UserId},
+    events::GameEvent,
+    loading::GameAssets,
+    menu::text_style,
+    names::PlayerNames,
+    palette::player_color,
+};
+
+const ENTRY_LIFETIME: Duration = Duration::from_secs(6);
+/// Entries fade out over the end of their lifetime.
+const FADE_TIME: f32 = 1.5;
+const MAX_ENTRIES: usize = 6;
+const NOTICE_TEXT: Color = Color::rgb(0.75, 0.75, 0.75);
+
+/// Where kill feed entries are listed, newest at the bottom.
+#[derive(Component)]
+pub struct KillFeed;
+
+#[derive(Component)]
+pub struct KillFeedEntry(Timer);
+
+pub fn spawn_kill_feed(mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.0),
+                    left: Val::Px(0.0),
+                    ..default()
+                },
+                size: Size::new(Val::Percent(100.0), Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(KillFeed)
+        .insert(InGameEntity);
+}
+
+pub fn add_kill_feed_entries(
+    mut game_events: EventReader<GameEvent>,
+    assets: Res<GameAssets>,
+    player_names: Res<PlayerNames>,
+    feed_query: Query<Entity, With<KillFeed>>,
+    entry_query: Query<(Entity, &KillFeedEntry)>,
+    mut commands: Commands,
+) {
+    let feed = match feed_query.get_single() {
+        Ok(feed) => feed,
+        Err(_) => return,
+    };
+
+    let events: Vec<&GameEvent> = game_events.iter().collect();
+    // someone who was shot has left too, but only the elimination is worth showing
+    let eliminated: HashSet<&UserId> = events
+        .iter()
+        .filter_map(|event| match event {
+            GameEvent::PlayerEliminated { victim, .. } => Some(victim),
+            _ => None,
+        })
+        .collect();
+
+    let name = |user_id: &UserId| {
+        TextSection::new(
+            player_names.get(user_id),
+            text_style(&assets, 18.0, player_color(user_id)),
+        )
+    };
+    let notice = |text: &str| TextSection::new(text, text_style(&assets, 18.0, NOTICE_TEXT));
+
+    let mut entries = vec![];
+    for event in events {
+        match event {
+            GameEvent::PlayerEliminated {
+                victim,
+                shooter: Some(shooter),
+            } if shooter != victim => {
+                entries.push(vec![name(shooter), notice(" shot "), name(victim)]);
+            }
+            GameEvent::PlayerEliminated { victim, .. } => {
+                entries.push(vec![name(victim), notice(" was eliminated")]);
+            }
+            GameEvent::PlayerJoined {
+                user_id,
+                initial: false,
+                ..
+            } => entries.push(vec![name(user_id), notice(" joined")]),
+            GameEvent::PlayerLeft { user_id, .. } if !eliminated.contains(user_id) => {
+                entries.push(vec![name(user_id), notice(" left")]);
+            }
+            _ => {}
+        }
+    }
+    if entries.is_empty() {
+        return;
+    }
+
+    // make room by dropping the oldest entries
+    let mut existing: Vec<(Entity, &KillFeedEntry)> = entry_query.iter().collect();
+    existing.sort_by_key(|(_, entry)| Reverse(entry.0.elapsed()));
+    let overflow = (existing.len() + entries.len()).saturating_sub(MAX_ENTRIES);
+    for (entity, _) in existing.into_iter().take(overflow) {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let skipped = entries.len().saturating_sub(MAX_ENTRIES);
+    commands.entity(feed).with_children(|parent| {
+        for sections in entries.into_iter().skip(skipped) {
+            parent
+                .spawn_bundle(TextBundle::from_sections(sections))
+                .insert(KillFeedEntry(Timer::new(ENTRY_LIFETIME, false)));
+        }
+    });
+}
+
+pub fn fade_kill_feed_entries(
+    time: Res<Time>,
+    mut entry_query: Query<(Entity, &mut KillFeedEntry, &mut Text)>,
+    mut commands: Commands,
+) {
+    for (entity, mut entry, mut text) in &mut entry_query {
+        entry.0.tick(time.delta());
+        if entry.0.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let remaining = (entry.0.duration() - entry.0.elapsed()).as_secs_f32();
+        let alpha = (remaining / FADE_TIME).min(1.);
+        for section in &mut text.sections {
+            if section.style.color.a() != alpha {
+                section.style.color.set_a(alpha);
+            }
+        }
+    }
+}
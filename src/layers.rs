@@ -0,0 +1,26 @@
+//! Z values for everything drawn in the world. Sprites are ordered by these
+//! rather than by the order they happen to be spawned in.
+
+use crate::serialization::{LayerPlacement, TileLayer};
+
+pub const FLOOR: f32 = 0.;
+pub const GROUND_DECORATION: f32 = 1.;
+pub const WALL: f32 = 2.;
+pub const PLAYER: f32 = 3.;
+pub const BULLET_TRAIL: f32 = 3.5;
+pub const BULLET: f32 = 4.;
+/// Muzzle flashes and sparks
+pub const PARTICLE: f32 = 4.5;
+pub const OVERHEAD_DECORATION: f32 = 5.;
+pub const FOG: f32 = 6.;
+/// Player name tags, hidden along with their player rather than covered by fog
+pub const NAME_TAG: f32 = 7.;
+/// World-space overlays such as the editor grid, above everything else
+pub const OVERLAY: f32 = 10.;
+
+pub fn decoration_z(layer: &TileLayer) -> f32 {
+    layer.z.unwrap_or(match layer.placement {
+        LayerPlacement::Ground => GROUND_DECORATION,
+        LayerPlacement::Overhead => OVERHEAD_DECORATION,
+    })
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::{query::AnyOf, system::EntityCommands},
+    prelude::*,
+};
+
+use crate::components::{BulletId, CurrentPlayer, InGameEntity, InterpolationBuffer, UserId};
+
+const SPAWN_TIME: Duration = Duration::from_millis(300);
+/// How big a player starts out when spawning in, relative to their full size.
+pub const SPAWN_START_SCALE: f32 = 0.4;
+/// How big an eliminated player gets before vanishing.
+const EXPLODE_END_SCALE: f32 = 1.8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeathAnimation {
+    /// Left the room
+    Fade,
+    /// Shot
+    Explode,
+    /// A bullet that expired, gone almost at once
+    QuickFade,
+}
+
+impl DeathAnimation {
+    fn duration(&self) -> Duration {
+        match self {
+            DeathAnimation::Fade => Duration::from_millis(400),
+            DeathAnimation::Explode => Duration::from_millis(350),
+            DeathAnimation::QuickFade => Duration::from_millis(100),
+        }
+    }
+}
+
+/// A player or bullet that's gone from the game state, animating out before
+/// it's despawned. It has already lost its `UserId` or `BulletId`, so it
+/// doesn't get updates and isn't counted as being in the room.
+#[derive(Component)]
+pub struct Dying {
+    animation: DeathAnimation,
+    timer: Timer,
+    start_alpha: f32,
+}
+
+#[derive(Component)]
+pub struct Spawning(Timer);
+
+impl Default for Spawning {
+    fn default() -> Self {
+        Spawning(Timer::new(SPAWN_TIME, false))
+    }
+}
+
+/// Takes an entity out of the game and starts its death animation.
+pub fn start_dying(entity: &mut EntityCommands, animation: DeathAnimation) {
+    entity
+        .remove::<UserId>()
+        .remove::<BulletId>()
+        .remove::<CurrentPlayer>()
+        .remove::<InterpolationBuffer>()
+        .remove::<Spawning>()
+        .insert(Dying {
+            animation,
+            timer: Timer::new(animation.duration(), false),
+            start_alpha: 1.,
+        })
+        .insert(InGameEntity);
+    // markers and indicators go right away
+    entity.despawn_descendants();
+}
+
+/// Players are drawn from an atlas and bullets from plain images, so either
+/// can be what's animating.
+fn sprite_color<'a>(
+    sprite: &'a mut Option<Mut<Sprite>>,
+    atlas_sprite: &'a mut Option<Mut<TextureAtlasSprite>>,
+) -> Option<&'a mut Color> {
+    match (sprite, atlas_sprite) {
+        (Some(sprite), _) => Some(&mut sprite.color),
+        (_, Some(sprite)) => Some(&mut sprite.color),
+        _ => None,
+    }
+}
+
+pub fn animate_dying(
+    time: Res<Time>,
+    mut dying_query: Query<(
+        Entity,
+        &mut Dying,
+        &mut Transform,
+        AnyOf<(&mut Sprite, &mut TextureAtlasSprite)>,
+    )>,
+    mut commands: Commands,
+) {
+    for (entity, mut dying, mut transform, (mut sprite, mut atlas_sprite)) in &mut dying_query {
+        let color = match sprite_color(&mut sprite, &mut atlas_sprite) {
+            Some(color) => color,
+            None => continue,
+        };
+        if dying.timer.elapsed().is_zero() {
+            dying.start_alpha = color.a();
+        }
+        dying.timer.tick(time.delta());
+        if dying.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let progress = dying.timer.percent();
+        color.set_a(dying.start_alpha * (1. - progress));
+        if dying.animation == DeathAnimation::Explode {
+            transform.scale = Vec3::splat(1. + (EXPLODE_END_SCALE - 1.) * progress);
+        }
+    }
+}
+
+pub fn animate_spawning(
+    time: Res<Time>,
+    mut spawning_query: Query<(
+        Entity,
+        &mut Spawning,
+        &mut Transform,
+        AnyOf<(&mut Sprite, &mut TextureAtlasSprite)>,
+    )>,
+    mut commands: Commands,
+) {
+    for (entity, mut spawning, mut transform, (mut sprite, mut atlas_sprite)) in &mut spawning_query
+    {
+        spawning.0.tick(time.delta());
+        // ease out, so the player pops in quickly and then settles
+        let progress = 1. - (1. - spawning.0.percent()).powi(2);
+        transform.scale = Vec3::splat(SPAWN_START_SCALE + (1. - SPAWN_START_SCALE) * progress);
+        if let Some(color) = sprite_color(&mut sprite, &mut atlas_sprite) {
+            color.set_a(progress);
+        }
+
+        if spawning.0.finished() {
+            commands.entity(entity).remove::<Spawning>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Instant};
+
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+
+    fn kill(app: &mut App, entity: Entity, animation: DeathAnimation) {
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &app.world);
+        start_dying(&mut commands.entity(entity), animation);
+        queue.apply(&mut app.world);
+    }
+
+    fn step_to(app: &mut App, instant: Instant) {
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(instant);
+        app.update();
+    }
+
+    #[test]
+    fn the_dying_leave_the_game_then_vanish_once_animated() {
+        let start = Instant::now();
+        let mut time = Time::default();
+        time.update_with_instant(start);
+        let mut app = App::new();
+        app.insert_resource(time).add_system(animate_dying);
+
+        let player = app
+            .world
+            .spawn()
+            .insert(UserId("me".to_string()))
+            .insert(CurrentPlayer)
+            .insert(InterpolationBuffer(VecDeque::new()))
+            .insert(Spawning::default())
+            .insert_bundle(SpriteBundle::default())
+            .id();
+        let ring = app.world.spawn().id();
+        app.world.entity_mut(player).push_children(&[ring]);
+        let bullet = app
+            .world
+            .spawn()
+            .insert(BulletId(1))
+            .insert_bundle(SpriteBundle::default())
+            .id();
+
+        kill(&mut app, player, DeathAnimation::Explode);
+        kill(&mut app, bullet, DeathAnimation::QuickFade);
+        let player_ref = app.world.entity(player);
+        assert!(!player_ref.contains::<UserId>());
+        assert!(!player_ref.contains::<CurrentPlayer>());
+        assert!(!player_ref.contains::<InterpolationBuffer>());
+        assert!(!player_ref.contains::<Spawning>());
+        assert!(player_ref.contains::<Dying>());
+        assert!(!app.world.entity(bullet).contains::<BulletId>());
+        assert!(app.world.get_entity(ring).is_none());
+
+        step_to(&mut app, start);
+        step_to(&mut app, start + Duration::from_millis(200));
+        // the bullet's quick fade is over, the player is mid-burst
+        assert!(app.world.get_entity(bullet).is_none());
+        let sprite = app
+            .world
+            .get::<Sprite>(player)
+            .expect("The player should still be animating");
+        assert!(sprite.color.a() > 0. && sprite.color.a() < 1.);
+        let scale = app
+            .world
+            .get::<Transform>(player)
+            .expect("The player should still be animating")
+            .scale
+            .x;
+        assert!(scale > 1. && scale < EXPLODE_END_SCALE, "{}", scale);
+
+        step_to(&mut app, start + Duration::from_millis(400));
+        assert!(app.world.get_entity(player).is_none());
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use bevy::{asset::LoadState, prelude::*};
+
+use crate::{
+    atlas::SpriteSheet,
+    menu::{spawn_screen, spawn_title, text_style, ERROR_TEXT},
+    serialization::MapAsset,
+    skins::{AssetName, AssetRegistry},
+    sound::SoundEffect,
+    AppState,
+};
+
+const BAR_WIDTH: f32 = 400.0;
+const BAR_BACKGROUND: Color = Color::rgb(0.25, 0.25, 0.3);
+const BAR_FILL: Color = Color::rgb(0.45, 0.6, 0.45);
+
+/// Handles to every asset the game uses, loaded up front so nothing is
+/// loaded per entity.
+pub struct GameAssets {
+    pub font: Handle<Font>,
+    pub player: Handle<SpriteSheet>,
+    pub bullet: Handle<Image>,
+    pub wall: Handle<Image>,
+    pub copy_icon: Handle<Image>,
+    pub map: Handle<MapAsset>,
+    /// Only the sounds the manifests list
+    pub sounds: HashMap<SoundEffect, Handle<AudioSource>>,
+    /// Floor and decoration images, by the path the map gives. Filled in once
+    /// the map has loaded
+    pub map_textures: HashMap<String, Handle<Image>>,
+}
+
+impl GameAssets {
+    pub fn load(registry: &AssetRegistry, asset_server: &AssetServer) -> Self {
+        GameAssets {
+            font: asset_server.load(registry.path(AssetName::Font)),
+            player: asset_server.load(registry.path(AssetName::Player)),
+            bullet: asset_server.load(registry.path(AssetName::Bullet)),
+            wall: asset_server.load(registry.path(AssetName::Wall)),
+            copy_icon: asset_server.load(registry.path(AssetName::CopyIcon)),
+            map: asset_server.load(registry.path(AssetName::Map)),
+            sounds: SoundEffect::ALL
+                .into_iter()
+                .filter_map(|effect| {
+                    let path = registry.get(effect.asset_name())?;
+                    Some((effect, asset_server.load(path)))
+                })
+                .collect(),
+            map_textures: HashMap::new(),
+        }
+    }
+
+    /// Swaps in the skin's handles, keeping the map that's already loaded.
+    pub fn reload_skin(&mut self, registry: &AssetRegistry, asset_server: &AssetServer) {
+        let reloaded = GameAssets::load(registry, asset_server);
+        self.font = reloaded.font;
+        self.player = reloaded.player;
+        self.bullet = reloaded.bullet;
+        self.wall = reloaded.wall;
+        self.copy_icon = reloaded.copy_icon;
+        self.sounds = reloaded.sounds;
+    }
+
+    /// The image for `name`, if it is one.
+    pub fn image(&self, name: AssetName) -> Option<Handle<Image>> {
+        match name {
+            AssetName::Bullet => Some(self.bullet.clone()),
+            AssetName::Wall => Some(self.wall.clone()),
+            AssetName::CopyIcon => Some(self.copy_icon.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn map_texture(&self, path: &str) -> Handle<Image> {
+        self.map_textures.get(path).cloned().unwrap_or_default()
+    }
+}
+
+/// Resolves asset names and starts loading everything.
+pub fn load_assets(
+    asset_server: Res<AssetServer>,
+    mut registry: ResMut<AssetRegistry>,
+    mut commands: Commands,
+) {
+    registry.resolve(asset_server.asset_io());
+    commands.insert_resource(GameAssets::load(&registry, &asset_server));
+}
+
+/// Where to go once loading is done.
+pub struct AfterLoading(pub AppState);
+
+/// Paths of the assets that failed to load, each reported once.
+#[derive(Default)]
+pub struct FailedAssets(pub Vec<String>);
+
+#[derive(Component)]
+pub struct LoadingBar;
+
+#[derive(Component)]
+pub struct LoadingText;
+
+pub fn spawn_loading_screen(assets: Res<GameAssets>, mut commands: Commands) {
+    spawn_screen(&mut commands, |parent| {
+        spawn_title(parent, &assets, "Loading");
+        parent
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(BAR_WIDTH), Val::Px(20.0)),
+                    margin: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                color: BAR_BACKGROUND.into(),
+                ..default()
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                            ..default()
+                        },
+                        color: BAR_FILL.into(),
+                        ..default()
+                    })
+                    .insert(LoadingBar);
+            });
+        parent
+            .spawn_bundle(TextBundle::from_section(
+                "",
+                text_style(&assets, 16.0, ERROR_TEXT),
+            ))
+            .insert(LoadingText);
+    });
+}
+
+/// Fills the progress bar as assets load and moves on once they all have.
+/// If any fail they're listed, and Enter carries on without them.
+pub fn track_loading(
+    asset_server: Res<AssetServer>,
+    mut assets: ResMut<GameAssets>,
+    maps: Res<Assets<MapAsset>>,
+    sheets: Res<Assets<SpriteSheet>>,
+    atlases: Res<Assets<TextureAtlas>>,
+    after_loading: Res<AfterLoading>,
+    keyboard: Res<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+    mut reported: ResMut<FailedAssets>,
+    mut bar_query: Query<&mut Style, With<LoadingBar>>,
+    mut text_query: Query<&mut Text, With<LoadingText>>,
+) {
+    // the map names more images, which can only be loaded once it's read
+    if let Some(map) = maps.get(&assets.map) {
+        if assets.map_textures.is_empty() {
+            let textures = map.floor.iter().chain(&map.decorations);
+            assets.map_textures = textures
+                .map(|layer| (layer.texture.clone(), asset_server.load(&layer.texture)))
+                .collect();
+        }
+    }
+
+    let mut handles: Vec<HandleUntyped> = vec![
+        assets.font.clone_untyped(),
+        assets.player.clone_untyped(),
+        assets.bullet.clone_untyped(),
+        assets.wall.clone_untyped(),
+        assets.copy_icon.clone_untyped(),
+        assets.map.clone_untyped(),
+    ];
+    // the sheet's image loads alongside it, not as part of it
+    if let Some(atlas) = sheets
+        .get(&assets.player)
+        .and_then(|sheet| atlases.get(&sheet.atlas))
+    {
+        handles.push(atlas.texture.clone_untyped());
+    }
+    handles.extend(assets.sounds.values().map(|sound| sound.clone_untyped()));
+    handles.extend(
+        assets
+            .map_textures
+            .values()
+            .map(|texture| texture.clone_untyped()),
+    );
+
+    let states: Vec<LoadState> = handles
+        .iter()
+        .map(|handle| asset_server.get_load_state(handle))
+        .collect();
+    let loaded = states
+        .iter()
+        .filter(|state| **state == LoadState::Loaded)
+        .count();
+    let failed: Vec<String> = handles
+        .iter()
+        .zip(&states)
+        .filter(|(_, state)| **state == LoadState::Failed)
+        .map(|(handle, _)| {
+            asset_server.get_handle_path(handle).map_or_else(
+                || "an unknown file".to_string(),
+                |path| path.path().display().to_string(),
+            )
+        })
+        .collect();
+
+    for mut style in &mut bar_query {
+        let progress = Val::Percent(100.0 * (loaded + failed.len()) as f32 / handles.len() as f32);
+        if style.size.width != progress {
+            style.size.width = progress;
+        }
+    }
+
+    let newly_failed: Vec<&String> = failed
+        .iter()
+        .filter(|path| !reported.0.contains(path))
+        .collect();
+    if !newly_failed.is_empty() {
+        for path in newly_failed {
+            error!("Failed to load {}", path);
+            reported.0.push(path.clone());
+        }
+        for mut text in &mut text_query {
+            text.sections[0].value = format!(
+                "Couldn't load {}\nPress Enter to carry on without them",
+                failed.join(", ")
+            );
+        }
+    }
+
+    let done = loaded + failed.len() == handles.len();
+    if done && (failed.is_empty() || keyboard.just_pressed(KeyCode::Return)) {
+        if let Err(e) = state.set(after_loading.0) {
+            warn!(
+                "Couldn't switch to {:?}. Error was {:?}",
+                after_loading.0, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, thread, time::Duration};
+
+    use bevy::{
+        asset::{AssetLoader, AssetPlugin, AssetServerSettings, LoadContext, LoadedAsset},
+        reflect::TypeUuid,
+        utils::BoxedFuture,
+    };
+
+    use super::*;
+    use crate::serialization::MapLoader;
+
+    /// Stands in for images, fonts and sprite sheets, which need a renderer.
+    #[derive(TypeUuid)]
+    #[uuid = "0b6f4a1e-3c52-4d8e-9f17-2a4c6e8b1d93"]
+    struct Placeholder;
+
+    #[derive(Default)]
+    struct PlaceholderLoader;
+
+    impl AssetLoader for PlaceholderLoader {
+        fn load<'a>(
+            &'a self,
+            _bytes: &'a [u8],
+            load_context: &'a mut LoadContext,
+        ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+            Box::pin(async move {
+                load_context.set_default_asset(LoadedAsset::new(Placeholder));
+                Ok(())
+            })
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["png", "ttf", "atlas.json"]
+        }
+    }
+
+    /// An assets directory where the wall image is listed but missing.
+    fn fake_assets() -> PathBuf {
+        let root = std::env::temp_dir().join("topdown-shooter-loading");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("Creating the test assets should work");
+        let files = [
+            (
+                "manifest.json",
+                r#"{"font": "font.ttf", "player": "player.atlas.json", "bullet": "bullet.png",
+                    "wall": "wall.png", "copy_icon": "copy.png", "map": "map.json"}"#,
+            ),
+            ("font.ttf", ""),
+            ("player.atlas.json", ""),
+            ("bullet.png", ""),
+            ("copy.png", ""),
+            (
+                "map.json",
+                r#"{"tileSize": 64, "top": 0, "left": 0, "bottom": 4, "right": 4, "walls": []}"#,
+            ),
+        ];
+        for (path, contents) in files {
+            fs::write(root.join(path), contents).expect("Creating the test assets should work");
+        }
+        root
+    }
+
+    fn failure_text(app: &mut App) -> String {
+        let mut text_query = app.world.query_filtered::<&Text, With<LoadingText>>();
+        text_query.single(&app.world).sections[0].value.clone()
+    }
+
+    #[test]
+    fn failed_assets_are_listed_once_and_enter_carries_on() {
+        let mut app = App::new();
+        app.insert_resource(AssetServerSettings {
+            asset_folder: fake_assets().to_string_lossy().into_owned(),
+            watch_for_changes: false,
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .add_asset::<Placeholder>()
+        .init_asset_loader::<PlaceholderLoader>()
+        .add_asset::<MapAsset>()
+        .init_asset_loader::<MapLoader>()
+        .add_asset::<SpriteSheet>()
+        .add_asset::<TextureAtlas>()
+        .insert_resource(AssetRegistry::new(None))
+        .insert_resource(AfterLoading(AppState::MainMenu))
+        .init_resource::<FailedAssets>()
+        .init_resource::<Input<KeyCode>>()
+        .add_state(AppState::Loading)
+        .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+        .add_system_set(SystemSet::on_update(AppState::Loading).with_system(track_loading));
+        app.world
+            .spawn()
+            .insert(Text::from_section("", TextStyle::default()))
+            .insert(LoadingText);
+
+        // loading happens on other threads
+        for _ in 0..200 {
+            app.update();
+            if !app.world.resource::<FailedAssets>().0.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        for _ in 0..10 {
+            app.update();
+        }
+
+        assert_eq!(app.world.resource::<FailedAssets>().0, ["wall.png"]);
+        assert_eq!(
+            failure_text(&mut app),
+            "Couldn't load wall.png\nPress Enter to carry on without them"
+        );
+        assert_eq!(
+            *app.world.resource::<State<AppState>>().current(),
+            AppState::Loading
+        );
+
+        app.world
+            .resource_mut::<Input<KeyCode>>()
+            .press(KeyCode::Return);
+        app.update();
+        assert_eq!(
+            *app.world.resource::<State<AppState>>().current(),
+            AppState::MainMenu
+        );
+    }
+}
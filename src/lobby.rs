@@ -0,0 +1,551 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    components::UserId,
+    connection::BackendError,
+    loading::GameAssets,
+    menu::{
+        spawn_button, spawn_screen, spawn_title, text_style, ConnectRequest, ConnectionError,
+        MenuButton, ERROR_TEXT, HOVERED_BUTTON, NORMAL_BUTTON, PRESSED_BUTTON,
+    },
+    systems::RoomId,
+    AppState,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_LISTED_ROOMS: usize = 8;
+/// How many rooms are remembered between runs.
+const MAX_SAVED_ROOMS: usize = 20;
+pub const RECENT_ROOMS_FILE: &str = "recent_rooms.json";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RoomInfo {
+    pub room_id: String,
+    /// The app the room belongs to, `None` for the one passed with `--app-id`
+    pub app_id: Option<String>,
+    /// How many players were in the room when it was last seen
+    pub player_count: Option<usize>,
+    pub created_at: Option<SystemTime>,
+}
+
+/// Where the lobby gets its list of rooms from. Listing may block, so it's
+/// only ever called from a task.
+pub trait RoomDirectory: Send + Sync {
+    fn list_rooms(&self) -> Result<Vec<RoomInfo>, BackendError>;
+}
+
+pub struct Rooms(pub Arc<dyn RoomDirectory>);
+
+/// Rooms created or joined on this computer, saved between runs when there's
+/// a file to save them to. The Hathora coordinator has no endpoint for
+/// listing rooms, so this is what the lobby shows until it does.
+#[derive(Default)]
+pub struct RecentRoomRegistry {
+    rooms: Mutex<Vec<RoomInfo>>,
+    path: Option<PathBuf>,
+}
+
+impl RecentRoomRegistry {
+    /// The rooms saved at `path`, or none if it can't be read.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let rooms = match &path {
+            Some(path) => match fs::read(path) {
+                Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                    warn!(
+                        "Failed to read recent rooms from {}. Error was {}",
+                        path.display(),
+                        e
+                    );
+                    vec![]
+                }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+                Err(e) => {
+                    warn!(
+                        "Failed to read recent rooms from {}. Error was {}",
+                        path.display(),
+                        e
+                    );
+                    vec![]
+                }
+            },
+            None => vec![],
+        };
+        RecentRoomRegistry {
+            rooms: Mutex::new(rooms),
+            path,
+        }
+    }
+
+    fn save(&self, rooms: &[RoomInfo]) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let json = serde_json::to_string_pretty(rooms).expect("Serialization should work");
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(path, json)),
+            None => fs::write(path, json),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to save recent rooms to {}. Error was {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    fn update(&self, room_id: &str, update: impl FnOnce(&mut RoomInfo)) {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .expect("Room registry lock shouldn't be poisoned");
+        let index = match rooms.iter().position(|room| room.room_id == room_id) {
+            Some(index) => index,
+            None => {
+                rooms.push(RoomInfo {
+                    room_id: room_id.to_string(),
+                    app_id: None,
+                    player_count: None,
+                    created_at: None,
+                });
+                rooms.len() - 1
+            }
+        };
+        update(&mut rooms[index]);
+
+        // the most recently visited room is listed first
+        let room = rooms.remove(index);
+        rooms.insert(0, room);
+        rooms.truncate(MAX_SAVED_ROOMS);
+        self.save(&rooms);
+    }
+
+    pub fn record_joined(&self, room_id: &str, app_id: Option<&str>, created: bool) {
+        self.update(room_id, |room| {
+            room.app_id = app_id.map(str::to_string);
+            if created {
+                room.created_at = Some(SystemTime::now());
+            }
+        });
+    }
+
+    pub fn record_player_count(&self, room_id: &str, player_count: usize) {
+        self.update(room_id, |room| room.player_count = Some(player_count));
+    }
+}
+
+impl RoomDirectory for RecentRoomRegistry {
+    fn list_rooms(&self) -> Result<Vec<RoomInfo>, BackendError> {
+        Ok(self
+            .rooms
+            .lock()
+            .expect("Room registry lock shouldn't be poisoned")
+            .clone())
+    }
+}
+
+/// The registry the game records visited rooms in. Usually the same one
+/// `Rooms` lists from.
+pub struct KnownRooms(pub Arc<RecentRoomRegistry>);
+
+pub fn record_joined_room(
+    known_rooms: Res<KnownRooms>,
+    room_id: Res<RoomId>,
+    request: Res<ConnectRequest>,
+) {
+    known_rooms.0.record_joined(
+        &room_id.0,
+        request.app_id.as_deref(),
+        request.room_id.is_none(),
+    );
+}
+
+pub fn record_player_count(
+    known_rooms: Res<KnownRooms>,
+    room_id: Res<RoomId>,
+    player_query: Query<(), With<UserId>>,
+    mut last_recorded: Local<Option<(String, usize)>>,
+) {
+    let player_count = player_query.iter().count();
+    let unchanged = last_recorded
+        .as_ref()
+        .is_some_and(|(id, count)| *id == room_id.0 && *count == player_count);
+    if unchanged {
+        return;
+    }
+
+    known_rooms.0.record_player_count(&room_id.0, player_count);
+    *last_recorded = Some((room_id.0.clone(), player_count));
+}
+
+pub struct RoomList {
+    refresh: Timer,
+    task: Option<Task<Result<Vec<RoomInfo>, BackendError>>>,
+}
+
+#[derive(Component)]
+pub struct RoomListNode;
+
+#[derive(Component)]
+pub struct RoomListStatus;
+
+const ROOM_LIST_HINT: &str =
+    "Rooms you've created or joined on this computer. Other players' rooms can't be listed, \
+     so ask them for a room ID or join link.";
+
+#[derive(Component)]
+pub enum LobbyButton {
+    Refresh,
+    Join {
+        room_id: String,
+        app_id: Option<String>,
+    },
+}
+
+pub fn spawn_lobby(assets: Res<GameAssets>, rooms: Res<Rooms>, mut commands: Commands) {
+    spawn_screen(&mut commands, |parent| {
+        spawn_title(parent, &assets, "Recent rooms");
+        parent.spawn_bundle(
+            TextBundle::from_section(ROOM_LIST_HINT, text_style(&assets, 16.0, Color::WHITE))
+                .with_style(Style {
+                    max_size: Size::new(Val::Px(600.0), Val::Undefined),
+                    margin: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                }),
+        );
+        parent
+            .spawn_bundle(TextBundle::from_section(
+                "Loading rooms...",
+                text_style(&assets, 18.0, Color::WHITE),
+            ))
+            .insert(RoomListStatus);
+        parent
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::ColumnReverse,
+                    align_items: AlignItems::Center,
+                    margin: UiRect::all(Val::Px(10.0)),
+                    ..default()
+                },
+                color: Color::NONE.into(),
+                ..default()
+            })
+            .insert(RoomListNode);
+        spawn_button(parent, &assets, "Refresh", LobbyButton::Refresh);
+        spawn_button(parent, &assets, "Back", MenuButton::BackToMenu);
+    });
+
+    commands.insert_resource(RoomList {
+        refresh: Timer::new(REFRESH_INTERVAL, true),
+        task: Some(list_rooms(&rooms)),
+    });
+}
+
+fn list_rooms(rooms: &Rooms) -> Task<Result<Vec<RoomInfo>, BackendError>> {
+    let directory = rooms.0.clone();
+    AsyncComputeTaskPool::get().spawn(async move { directory.list_rooms() })
+}
+
+pub fn refresh_room_list(
+    assets: Res<GameAssets>,
+    rooms: Res<Rooms>,
+    time: Res<Time>,
+    mut room_list: ResMut<RoomList>,
+    list_query: Query<Entity, With<RoomListNode>>,
+    mut status_query: Query<&mut Text, With<RoomListStatus>>,
+    mut commands: Commands,
+) {
+    room_list.refresh.tick(time.delta());
+    if room_list.refresh.just_finished() && room_list.task.is_none() {
+        room_list.task = Some(list_rooms(&rooms));
+    }
+
+    let result = match &mut room_list.task {
+        Some(task) => match future::block_on(future::poll_once(task)) {
+            Some(result) => result,
+            None => return,
+        },
+        None => return,
+    };
+    room_list.task = None;
+
+    let (status, status_color, listed) = match result {
+        Ok(listed) if listed.is_empty() => (
+            "No rooms yet. Rooms you create or join show up here".to_string(),
+            Color::WHITE,
+            listed,
+        ),
+        Ok(listed) => (String::new(), Color::WHITE, listed),
+        Err(e) => {
+            warn!("Failed to list rooms. Error was {}", e);
+            (format!("Couldn't list rooms: {}", e), ERROR_TEXT, vec![])
+        }
+    };
+    for mut text in &mut status_query {
+        text.sections[0].value = status.clone();
+        text.sections[0].style.color = status_color;
+    }
+
+    let now = SystemTime::now();
+    for list in &list_query {
+        commands.entity(list).despawn_descendants();
+        commands.entity(list).with_children(|parent| {
+            for room in listed.iter().take(MAX_LISTED_ROOMS) {
+                spawn_room_entry(parent, &assets, room, now);
+            }
+        });
+    }
+}
+
+fn spawn_room_entry(
+    parent: &mut ChildBuilder,
+    assets: &GameAssets,
+    room: &RoomInfo,
+    now: SystemTime,
+) {
+    let mut label = room.room_id.clone();
+    if let Some(player_count) = room.player_count {
+        let plural = if player_count == 1 { "" } else { "s" };
+        label += &format!(" | {} player{} when last seen", player_count, plural);
+    }
+    if let Some(age) = room
+        .created_at
+        .and_then(|created_at| now.duration_since(created_at).ok())
+    {
+        label += &format!(" | created {}", format_age(age));
+    }
+
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(600.0), Val::Px(36.0)),
+                margin: UiRect::all(Val::Px(4.0)),
+                padding: UiRect::all(Val::Px(8.0)),
+                align_items: AlignItems::Center,
+                overflow: Overflow::Hidden,
+                ..default()
+            },
+            color: NORMAL_BUTTON.into(),
+            ..default()
+        })
+        .insert(LobbyButton::Join {
+            room_id: room.room_id.clone(),
+            app_id: room.app_id.clone(),
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                label,
+                text_style(assets, 16.0, Color::WHITE),
+            ));
+        });
+}
+
+fn format_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    if minutes < 1 {
+        "just now".to_string()
+    } else if minutes < 60 {
+        format!("{}m ago", minutes)
+    } else {
+        format!("{}h ago", minutes / 60)
+    }
+}
+
+pub fn lobby_buttons(
+    mut interaction_query: Query<(&Interaction, &LobbyButton, &mut UiColor), Changed<Interaction>>,
+    rooms: Res<Rooms>,
+    mut room_list: ResMut<RoomList>,
+    mut state: ResMut<State<AppState>>,
+    mut commands: Commands,
+) {
+    for (interaction, button, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Clicked => {
+                *color = PRESSED_BUTTON.into();
+                match button {
+                    LobbyButton::Refresh => {
+                        if room_list.task.is_none() {
+                            room_list.task = Some(list_rooms(&rooms));
+                            room_list.refresh.reset();
+                        }
+                    }
+                    LobbyButton::Join { room_id, app_id } => {
+                        commands.insert_resource(ConnectRequest {
+                            room_id: Some(room_id.clone()),
+                            app_id: app_id.clone(),
+                        });
+                        commands.remove_resource::<ConnectionError>();
+                        if let Err(e) = state.set(AppState::Connecting) {
+                            warn!(
+                                "Couldn't switch to {:?}. Error was {:?}",
+                                AppState::Connecting,
+                                e
+                            );
+                        }
+                        return;
+                    }
+                }
+            }
+            Interaction::Hovered => *color = HOVERED_BUTTON.into(),
+            Interaction::None => *color = NORMAL_BUTTON.into(),
+        }
+    }
+}
+
+pub fn close_lobby(mut commands: Commands) {
+    commands.remove_resource::<RoomList>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed_ids(registry: &RecentRoomRegistry) -> Vec<String> {
+        registry
+            .list_rooms()
+            .expect("Listing the registry should work")
+            .into_iter()
+            .map(|room| room.room_id)
+            .collect()
+    }
+
+    fn listed(registry: &RecentRoomRegistry, room_id: &str) -> RoomInfo {
+        registry
+            .list_rooms()
+            .expect("Listing the registry should work")
+            .into_iter()
+            .find(|room| room.room_id == room_id)
+            .expect("Room should be listed")
+    }
+
+    #[test]
+    fn the_most_recent_room_is_listed_first() {
+        let registry = RecentRoomRegistry::default();
+        assert!(listed_ids(&registry).is_empty());
+
+        registry.record_joined("first", None, true);
+        registry.record_joined("second", None, false);
+        registry.record_joined("third", None, false);
+        assert_eq!(listed_ids(&registry), ["third", "second", "first"]);
+
+        // going back to a room moves it up rather than listing it twice
+        registry.record_joined("first", None, false);
+        assert_eq!(listed_ids(&registry), ["first", "third", "second"]);
+        registry.record_player_count("second", 2);
+        assert_eq!(listed_ids(&registry), ["second", "first", "third"]);
+    }
+
+    #[test]
+    fn only_created_rooms_have_a_creation_time() {
+        let registry = RecentRoomRegistry::default();
+        registry.record_joined("created", None, true);
+        registry.record_joined("joined", None, false);
+        assert!(listed(&registry, "created").created_at.is_some());
+        assert!(listed(&registry, "joined").created_at.is_none());
+
+        // rejoining a room doesn't forget when it was created
+        registry.record_joined("created", None, false);
+        assert!(listed(&registry, "created").created_at.is_some());
+    }
+
+    #[test]
+    fn player_counts_keep_the_latest() {
+        let registry = RecentRoomRegistry::default();
+        registry.record_joined("room", None, true);
+        assert_eq!(listed(&registry, "room").player_count, None);
+
+        registry.record_player_count("room", 1);
+        registry.record_player_count("room", 3);
+        assert_eq!(listed(&registry, "room").player_count, Some(3));
+        assert_eq!(listed_ids(&registry), ["room"]);
+
+        // a count for a room that was never joined still lists it
+        registry.record_player_count("elsewhere", 0);
+        assert_eq!(listed(&registry, "elsewhere").player_count, Some(0));
+        assert!(listed(&registry, "elsewhere").created_at.is_none());
+    }
+
+    #[test]
+    fn rooms_remember_which_app_they_are_in() {
+        let registry = RecentRoomRegistry::default();
+        registry.record_joined("linked", Some("other-app"), false);
+        registry.record_joined("local", None, true);
+        assert_eq!(
+            listed(&registry, "linked").app_id.as_deref(),
+            Some("other-app")
+        );
+        assert_eq!(listed(&registry, "local").app_id, None);
+
+        // player counts don't lose track of it
+        registry.record_player_count("linked", 2);
+        assert_eq!(
+            listed(&registry, "linked").app_id.as_deref(),
+            Some("other-app")
+        );
+    }
+
+    #[test]
+    fn recent_rooms_are_saved_between_runs() {
+        let path = std::env::temp_dir()
+            .join("topdown-shooter-recent-rooms")
+            .join(RECENT_ROOMS_FILE);
+        let _ = fs::remove_file(&path);
+
+        let registry = RecentRoomRegistry::load(Some(path.clone()));
+        assert!(listed_ids(&registry).is_empty());
+        registry.record_joined("first", None, true);
+        registry.record_joined("linked", Some("other-app"), false);
+        registry.record_player_count("linked", 2);
+
+        let reloaded = RecentRoomRegistry::load(Some(path.clone()));
+        assert_eq!(
+            reloaded
+                .list_rooms()
+                .expect("Listing the registry should work"),
+            registry
+                .list_rooms()
+                .expect("Listing the registry should work")
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unreadable_saves_start_empty() {
+        let path = std::env::temp_dir().join("topdown-shooter-malformed-rooms.json");
+        fs::write(&path, "[{\"room_id\": 3").expect("Writing the test file should work");
+        assert!(listed_ids(&RecentRoomRegistry::load(Some(path.clone()))).is_empty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn only_the_latest_rooms_are_kept() {
+        let registry = RecentRoomRegistry::default();
+        for i in 0..MAX_SAVED_ROOMS + 5 {
+            registry.record_joined(&format!("room-{}", i), None, false);
+        }
+        let ids = listed_ids(&registry);
+        assert_eq!(ids.len(), MAX_SAVED_ROOMS);
+        assert_eq!(ids[0], format!("room-{}", MAX_SAVED_ROOMS + 4));
+    }
+
+    #[test]
+    fn ages_are_rounded_down() {
+        assert_eq!(format_age(Duration::from_secs(59)), "just now");
+        assert_eq!(format_age(Duration::from_secs(61)), "1m ago");
+        assert_eq!(format_age(Duration::from_secs(3599)), "59m ago");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2h ago");
+    }
+}
@@ -1,59 +1,549 @@
-use bevy::prelude::*;
+// Bevy systems take everything they use as parameters and queries
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
-use clap::Parser;
+use std::{path::PathBuf, sync::Arc};
 
+use bevy::{prelude::*, window::WindowMode};
+
+use clap::{Parser, Subcommand};
+
+use atlas::*;
+use camera::*;
+use chat::*;
+use connection::*;
+use copy_paste::{FallbackClipboard, GameClipboard, SystemClipboard};
+use diff::PreviousState;
+use editor::*;
+use events::{ChatReceived, GameEvent, LocalPlayerEvent, StatsReported};
+use fog::*;
+use join_link::JoinTarget;
+use kill_feed::*;
+use lifecycle::*;
+use loading::*;
+use lobby::*;
+use mapgen::{GeneratorParams, MapStyle, SeededRng};
+use menu::*;
+use minimap::*;
+use names::*;
+use palette::*;
+use particles::*;
+use scoreboard::*;
 use serialization::{MapAsset, MapLoader};
+use skins::*;
+use sound::*;
+use spectator::*;
 use systems::*;
 
+mod atlas;
+mod camera;
+mod chat;
 mod components;
+mod config;
+mod connection;
+mod copy_paste;
+mod diff;
+mod editor;
+mod events;
+#[cfg(test)]
+mod fakes;
+mod fog;
+mod join_link;
+mod kill_feed;
+mod layers;
+mod lifecycle;
+mod loading;
+mod lobby;
+mod mapgen;
+mod menu;
+mod minimap;
+mod names;
+mod palette;
+mod particles;
+mod scoreboard;
 mod serialization;
+mod skins;
+mod sound;
+mod spectator;
 mod systems;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    room_id: Option<String>,
+    /// Join this room right away instead of showing the main menu. Takes a
+    /// room ID or a topdown-shooter://join/<room_id>?app=<app_id> link
+    room_id: Option<JoinTarget>,
 
     #[arg(short, long)]
     app_id: Option<String>,
+
+    /// Log in with this nickname instead of anonymously
+    #[arg(short, long, value_parser = validate_nickname)]
+    name: Option<String>,
+
+    /// Open the map at this path in the map editor instead of joining a room
+    #[arg(long, value_name = "PATH")]
+    edit_map: Option<PathBuf>,
+
+    /// Side length of the minimap in pixels. 0 hides it
+    #[arg(long, default_value_t = 180.)]
+    minimap_size: f32,
+
+    /// Opacity of the minimap, from 0 to 1
+    #[arg(long, default_value_t = 0.6)]
+    minimap_opacity: f32,
+
+    #[arg(long, value_enum, default_value_t = MinimapRotation::NorthUp)]
+    minimap_rotation: MinimapRotation,
+
+    #[arg(long, value_enum, default_value_t = MinimapCorner::TopRight)]
+    minimap_corner: MinimapCorner,
+
+    /// Show bullets on the minimap
+    #[arg(long)]
+    minimap_bullets: bool,
+
+    /// Only show what the local player has line of sight to
+    #[arg(long)]
+    fog_of_war: bool,
+
+    /// Roughly how long the camera takes to catch up with the player, in seconds
+    #[arg(long, default_value_t = 0.15)]
+    camera_smoothing: f32,
+
+    /// How far the camera leans toward the cursor, in pixels
+    #[arg(long, default_value_t = 120.)]
+    camera_look_ahead: f32,
+
+    /// How far the player can move before the camera follows, in pixels
+    #[arg(long, default_value_t = 16.)]
+    camera_deadzone: f32,
+
+    /// How the visible area of the world adapts to the window's size
+    #[arg(long, value_enum, default_value_t = ViewScaling::Auto)]
+    scaling: ViewScaling,
+
+    /// Start in borderless fullscreen. F11 toggles it while playing
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Watch the room without playing. No inputs are sent to the server
+    #[arg(long)]
+    spectate: bool,
+
+    /// Turn off screen shake
+    #[arg(long)]
+    no_screen_shake: bool,
+
+    /// How many particles muzzle flashes, bullet trails and sparks use
+    #[arg(long, value_enum, default_value_t = ParticleQuality::High)]
+    particles: ParticleQuality,
+
+    /// Most particles alive at once
+    #[arg(long, default_value_t = 600)]
+    particle_budget: usize,
+
+    /// Start with the skin pack in assets/skins/<SKIN>. F7 switches packs while playing
+    #[arg(long)]
+    skin: Option<String>,
+
+    /// Play no sound at all
+    #[arg(long)]
+    no_sound: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-pub struct ProvidedRoomId(Option<String>);
-pub struct ProvidedAppId(Option<String>);
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a random arena in the map JSON format
+    GenerateMap(GenerateMapArgs),
+}
+
+#[derive(clap::Args)]
+struct GenerateMapArgs {
+    /// The same seed and size always produce the same map
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Width of the map in tiles
+    #[arg(long, default_value_t = 17, value_parser = clap::value_parser!(i32).range(8..))]
+    width: i32,
+
+    /// Height of the map in tiles
+    #[arg(long, default_value_t = 33, value_parser = clap::value_parser!(i32).range(8..))]
+    height: i32,
+
+    /// Size of a tile in world units
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(i32).range(1..))]
+    tile_size: i32,
+
+    #[arg(long, value_enum, default_value_t = MapStyle::Symmetric)]
+    style: MapStyle,
+
+    /// Where to write the map. Printed to stdout if omitted
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AppState {
+    /// Loading every asset before anything is shown
+    Loading,
+    MainMenu,
+    /// Picking a recently visited room to rejoin
+    Lobby,
+    /// Logging in and joining or creating the room in `ConnectRequest`
+    Connecting,
+    InGame,
+    /// The connection to the room was lost
+    Disconnected,
+}
 
 fn main() {
     let args = Args::parse();
 
+    if let Some(Command::GenerateMap(generate_args)) = args.command {
+        generate_map(generate_args);
+        return;
+    }
+
+    if let Some(path) = args.edit_map {
+        run_editor(path);
+        return;
+    }
+
+    // a room ID on the command line skips the menu
+    let after_loading = if args.room_id.is_some() {
+        AppState::Connecting
+    } else {
+        AppState::MainMenu
+    };
+
+    let known_rooms = Arc::new(RecentRoomRegistry::load(config::config_path(
+        RECENT_ROOMS_FILE,
+    )));
+    let audio_queue = BevyAudioQueue::default();
+
     App::new()
         .insert_resource(WindowDescriptor {
             width: 800.,
             height: 600.,
             title: "bevy-topdown-shooter".to_string(),
-            resizable: false,
+            resizable: true,
+            mode: if args.fullscreen {
+                WindowMode::BorderlessFullscreen
+            } else {
+                WindowMode::Windowed
+            },
             ..default()
         })
         .add_plugins(DefaultPlugins)
         .add_asset::<MapAsset>()
         .init_asset_loader::<MapLoader>()
-        .insert_resource(ProvidedRoomId(args.room_id))
-        .insert_resource(ProvidedAppId(args.app_id))
-        // This is exclusive so we can guarantee that the room is created before
-        // we render the room ID
-        .add_startup_system(log_in_and_set_up_transport.exclusive_system())
+        .add_asset::<SpriteSheet>()
+        .init_asset_loader::<SpriteSheetLoader>()
+        .add_event::<LocalPlayerEvent>()
+        .add_event::<GameEvent>()
+        .add_event::<StatsReported>()
+        .add_event::<ChatReceived>()
+        .insert_resource(ConnectRequest {
+            room_id: args.room_id.as_ref().map(|target| target.room_id.clone()),
+            app_id: args.room_id.and_then(|target| target.app_id),
+        })
+        .insert_resource(Backend::hathora(
+            args.app_id.unwrap_or_else(|| DEFAULT_APP_ID.to_string()),
+        ))
+        .insert_resource(MenuFields {
+            nickname: args.name.clone().unwrap_or_default(),
+            ..default()
+        })
+        .insert_resource(Nickname(args.name))
+        .init_resource::<PlayerNames>()
+        .init_resource::<PreviousState>()
+        .init_resource::<Chat>()
+        .init_resource::<Scoreboard>()
+        .init_resource::<ScoreboardSort>()
+        .insert_resource(GameClipboard(Box::new(FallbackClipboard::new(
+            SystemClipboard,
+        ))))
+        .insert_resource(KnownRooms(known_rooms.clone()))
+        .insert_resource(Rooms(known_rooms))
+        .insert_resource(MinimapSettings {
+            size: args.minimap_size,
+            opacity: args.minimap_opacity.clamp(0., 1.),
+            rotation: args.minimap_rotation,
+            corner: args.minimap_corner,
+            show_bullets: args.minimap_bullets,
+        })
+        .insert_resource(FogOfWarSettings {
+            enabled: args.fog_of_war,
+        })
+        .insert_resource(CameraSettings {
+            smooth_time: args.camera_smoothing.max(0.),
+            look_ahead: args.camera_look_ahead.max(0.),
+            deadzone: args.camera_deadzone.max(0.),
+            scaling: args.scaling,
+            screen_shake: !args.no_screen_shake,
+        })
+        .insert_resource(ParticleSettings {
+            quality: args.particles,
+            budget: args.particle_budget,
+        })
+        .insert_resource(ParticleRng(SeededRng::new(0)))
+        .insert_resource(Spectator {
+            forced: args.spectate,
+            ..default()
+        })
+        .insert_resource(AssetRegistry::new(args.skin))
+        .insert_resource(VolumeSettings::load())
+        .insert_resource(SoundOutput(if args.no_sound {
+            Box::new(NullSink::default())
+        } else {
+            Box::new(BevyAudioSink(audio_queue.clone()))
+        }))
+        .insert_resource(audio_queue)
+        .insert_resource(AfterLoading(after_loading))
+        .init_resource::<FailedAssets>()
+        .add_state(AppState::Loading)
+        // everything else uses the handles, so loading starts first
+        .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
         .add_startup_system(setup_camera)
-        .add_startup_system(display_room_id)
-        .add_startup_system(load_map)
+        .add_startup_system(setup_minimap)
+        .add_startup_system(create_local_player_ring)
         // general systems
-        .add_system(bevy::window::close_on_esc)
-        .add_system(draw_map)
-        .add_system(copy_room_id_button)
+        // Esc while typing only closes the chat
+        .add_system(
+            bevy::window::close_on_esc
+                .with_run_criteria(chat_closed)
+                .before(chat_input),
+        )
+        .add_system(toggle_fullscreen)
+        .add_system(zoom_camera.before(update_camera))
+        .add_system(cycle_skins)
+        .add_system(apply_skin.after(cycle_skins))
+        // loading
+        .add_system_set(SystemSet::on_enter(AppState::Loading).with_system(spawn_loading_screen))
+        .add_system_set(SystemSet::on_update(AppState::Loading).with_system(track_loading))
+        .add_system_set(
+            SystemSet::on_exit(AppState::Loading)
+                .with_system(despawn_menu_screen)
+                .with_system(draw_map)
+                .with_system(draw_minimap)
+                .with_system(spawn_fog),
+        )
+        // menus
+        .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(spawn_main_menu))
+        .add_system_set(
+            SystemSet::on_update(AppState::MainMenu)
+                .with_system(edit_menu_fields)
+                .with_system(menu_buttons.after(edit_menu_fields))
+                .with_system(adjust_volume),
+        )
+        .add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(despawn_menu_screen))
+        .add_system_set(SystemSet::on_enter(AppState::Lobby).with_system(spawn_lobby))
+        .add_system_set(
+            SystemSet::on_update(AppState::Lobby)
+                .with_system(refresh_room_list)
+                .with_system(lobby_buttons)
+                .with_system(menu_buttons),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::Lobby)
+                .with_system(despawn_menu_screen)
+                .with_system(close_lobby),
+        )
+        .add_system_set(
+            SystemSet::on_enter(AppState::Connecting)
+                .with_system(spawn_connecting_screen)
+                .with_system(start_connecting),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Connecting)
+                .with_system(poll_connection)
+                .with_system(menu_buttons),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::Connecting)
+                .with_system(despawn_menu_screen)
+                .with_system(cancel_connection),
+        )
+        .add_system_set(
+            SystemSet::on_enter(AppState::Disconnected).with_system(spawn_disconnected_screen),
+        )
+        .add_system_set(SystemSet::on_update(AppState::Disconnected).with_system(menu_buttons))
+        .add_system_set(SystemSet::on_exit(AppState::Disconnected).with_system(despawn_menu_screen))
         // game state systems
-        .add_system(read_from_server)
+        .add_system_set(
+            SystemSet::on_enter(AppState::InGame)
+                .with_system(display_room_id)
+                .with_system(display_spectator_status)
+                .with_system(spawn_kill_feed)
+                .with_system(spawn_chat_panel)
+                .with_system(record_joined_room),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::InGame)
+                .with_system(copy_room_id_button)
+                .with_system(read_from_server)
+                .with_system(chat_input)
+                .with_system(receive_chat_messages.after(read_from_server))
+                .with_system(
+                    update_chat_panel
+                        .after(chat_input)
+                        .after(receive_chat_messages),
+                )
+                .with_system(
+                    write_inputs
+                        .after(read_from_server)
+                        .after(copy_room_id_button)
+                        .after(chat_input),
+                )
+                .with_system(cycle_spectator_target.after(read_from_server))
+                .with_system(apply_game_events.after(read_from_server))
+                .with_system(detect_local_player_events.after(read_from_server))
+                .with_system(add_kill_feed_entries.after(read_from_server))
+                .with_system(fade_kill_feed_entries)
+                .with_system(play_sound_effects.after(read_from_server))
+                .with_system(record_player_count.after(read_from_server))
+                .with_system(update_scoreboard.after(read_from_server))
+                .with_system(
+                    show_scoreboard
+                        .after(update_scoreboard)
+                        .before(write_inputs),
+                ),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::InGame)
+                .with_system(leave_room)
+                .with_system(reset_scoreboard)
+                .with_system(reset_chat),
+        )
         .add_system(update_position_from_interpolation_buffer.after(read_from_server))
+        .add_system(output_bevy_audio.after(play_sound_effects))
+        .add_system(
+            add_camera_trauma
+                .after(detect_local_player_events)
+                .after(write_inputs),
+        )
+        .add_system(
+            update_camera
+                .after(update_position_from_interpolation_buffer)
+                .after(cycle_spectator_target)
+                .after(add_camera_trauma),
+        )
+        // player styling
+        .add_system(add_local_player_indicator)
+        .add_system(spawn_name_tags)
+        .add_system(
+            update_name_tags
+                .after(update_position_from_interpolation_buffer)
+                .after(hide_unseen_entities),
+        )
+        // spawn and death animations
+        .add_system(
+            animate_player_sprites
+                .after(read_from_server)
+                .after(update_position_from_interpolation_buffer),
+        )
+        .add_system(animate_spawning)
+        .add_system(animate_dying)
+        // particles
         .add_system(
-            write_inputs
+            emit_particles
                 .after(read_from_server)
-                .after(copy_room_id_button),
+                .after(hide_unseen_entities),
         )
-        .add_system(update_camera.after(update_position_from_interpolation_buffer))
+        .add_system(update_particles)
+        // minimap
+        .add_system(add_minimap_markers.after(read_from_server))
+        .add_system(update_minimap_camera.after(update_position_from_interpolation_buffer))
+        // fog of war
+        .add_system(update_fog.after(update_position_from_interpolation_buffer))
+        .add_system(
+            hide_unseen_entities
+                .after(update_position_from_interpolation_buffer)
+                .after(add_minimap_markers),
+        )
+        .run();
+}
+
+fn run_editor(path: PathBuf) {
+    // a map that can't be read is reported before the window opens, rather
+    // than replaced with a blank one that would overwrite it on save
+    let map = match EditorMap::open(&path) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Couldn't open {}. Error was {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    App::new()
+        .insert_resource(WindowDescriptor {
+            width: 800.,
+            height: 600.,
+            title: format!("bevy-topdown-shooter - editing {}", path.display()),
+            resizable: true,
+            ..default()
+        })
+        .add_plugins(DefaultPlugins)
+        // load_assets loads the game's full handle set, the map and sprite
+        // sheet included
+        .add_asset::<MapAsset>()
+        .init_asset_loader::<MapLoader>()
+        .add_asset::<SpriteSheet>()
+        .init_asset_loader::<SpriteSheetLoader>()
+        .insert_resource(EditorPath(path))
+        .insert_resource(map)
+        .insert_resource(AssetRegistry::new(None))
+        .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+        .add_startup_system(load_editor_map)
+        .add_startup_system(display_editor_help)
+        .add_system(bevy::window::close_on_esc)
+        .add_system(toggle_fullscreen)
+        .add_system(zoom_camera)
+        .add_system(editor_camera_controls)
+        .add_system(paint_tiles)
+        .add_system(edit_bounds)
+        .add_system(editor_shortcuts.after(paint_tiles))
+        .add_system(draw_editor_tiles.after(editor_shortcuts).after(edit_bounds))
+        .add_system(draw_grid.after(editor_shortcuts).after(edit_bounds))
         .run();
 }
+
+fn generate_map(args: GenerateMapArgs) {
+    let map = mapgen::generate(&GeneratorParams {
+        seed: args.seed,
+        width: args.width,
+        height: args.height,
+        tile_size: args.tile_size,
+        style: args.style,
+    });
+    let json = serde_json::to_string_pretty(&map).expect("Serialization should work");
+
+    match args.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, json) {
+                eprintln!("Couldn't write {}. Error was {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        // stdout only ever has the map on it, so it can be piped into a file
+        None => println!("{}", json),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_tiles_must_have_a_size() {
+        for tile_size in ["0", "-64"] {
+            let args = Args::try_parse_from(["shooter", "generate-map", "--tile-size", tile_size]);
+            assert!(args.is_err(), "--tile-size {} was accepted", tile_size);
+        }
+        assert!(Args::try_parse_from(["shooter", "generate-map", "--tile-size", "32"]).is_ok());
+    }
+}
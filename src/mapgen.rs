@@ -0,0 +1,417 @@
+use std::collections::{HashSet, VecDeque};
+
+use clap::ValueEnum;
+
+use crate::serialization::{merge_tiles, MapAsset};
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapStyle {
+    /// Rectangular rooms carved out of solid rock, joined by corridors
+    Rooms,
+    /// An open arena with cover mirrored into all four quadrants
+    Symmetric,
+}
+
+pub struct GeneratorParams {
+    pub seed: u64,
+    pub width: i32,
+    pub height: i32,
+    pub tile_size: i32,
+    pub style: MapStyle,
+}
+
+/// SplitMix64. The generator has to produce identical maps for a seed on every
+/// platform and release, so it doesn't lean on an external RNG crate whose
+/// output could change underneath us.
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `low..high`. Returns `low` when the range is empty.
+    pub fn range(&mut self, low: i32, high: i32) -> i32 {
+        if high <= low {
+            return low;
+        }
+        low + (self.next_u64() % (high - low) as u64) as i32
+    }
+
+    pub fn chance(&mut self, numerator: u64, denominator: u64) -> bool {
+        self.next_u64() % denominator < numerator
+    }
+}
+
+/// Wall occupancy for a `width` x `height` map whose top-left tile is `(0, 0)`.
+struct Grid {
+    width: i32,
+    height: i32,
+    walls: Vec<bool>,
+}
+
+impl Grid {
+    fn new(width: i32, height: i32, filled: bool) -> Self {
+        Grid {
+            width,
+            height,
+            walls: vec![filled; (width * height) as usize],
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && x < self.width && y >= 0 && y < self.height
+    }
+
+    fn is_wall(&self, x: i32, y: i32) -> bool {
+        self.walls[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: i32, y: i32, wall: bool) {
+        if self.contains(x, y) {
+            self.walls[(y * self.width + x) as usize] = wall;
+        }
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, wall: bool) {
+        for dx in x..x + width {
+            for dy in y..y + height {
+                self.set(dx, dy, wall);
+            }
+        }
+    }
+
+    /// Walls in the outermost ring of tiles, so no one can leave the map.
+    fn wall_border(&mut self) {
+        self.fill_rect(0, 0, self.width, 1, true);
+        self.fill_rect(0, self.height - 1, self.width, 1, true);
+        self.fill_rect(0, 0, 1, self.height, true);
+        self.fill_rect(self.width - 1, 0, 1, self.height, true);
+    }
+
+    fn wall_tiles(&self) -> HashSet<(i32, i32)> {
+        let mut tiles = HashSet::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_wall(x, y) {
+                    tiles.insert((x, y));
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Splits the open tiles into 4-connected regions. Diagonal gaps between
+    /// walls are too narrow to walk through, so they don't count as connections.
+    fn open_regions(&self) -> Vec<Vec<(i32, i32)>> {
+        let mut seen = vec![false; self.walls.len()];
+        let mut regions = Vec::new();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = (y * self.width + x) as usize;
+                if self.walls[index] || seen[index] {
+                    continue;
+                }
+
+                let mut region = Vec::new();
+                let mut queue = VecDeque::from([(x, y)]);
+                seen[index] = true;
+                while let Some((cx, cy)) = queue.pop_front() {
+                    region.push((cx, cy));
+                    for (nx, ny) in [(cx + 1, cy), (cx - 1, cy), (cx, cy + 1), (cx, cy - 1)] {
+                        if !self.contains(nx, ny) {
+                            continue;
+                        }
+                        let neighbour = (ny * self.width + nx) as usize;
+                        if !self.walls[neighbour] && !seen[neighbour] {
+                            seen[neighbour] = true;
+                            queue.push_back((nx, ny));
+                        }
+                    }
+                }
+                regions.push(region);
+            }
+        }
+
+        regions
+    }
+
+    /// Keeps the largest open region and walls off everything else, so no
+    /// player can end up somewhere unreachable.
+    fn seal_pockets(&mut self) {
+        let mut regions = self.open_regions();
+        regions.sort_by_key(|region| std::cmp::Reverse(region.len()));
+        for region in regions.iter().skip(1) {
+            for &(x, y) in region {
+                self.set(x, y, true);
+            }
+        }
+    }
+}
+
+/// Generates a map in the same format `MapLoader` reads. The output depends
+/// only on `params`, so a seed can be shared to reproduce an arena.
+///
+/// Panics if the map is smaller than 8x8 tiles.
+pub fn generate(params: &GeneratorParams) -> MapAsset {
+    assert!(
+        params.width >= 8 && params.height >= 8,
+        "Generated maps should be at least 8x8 tiles"
+    );
+
+    let mut rng = SeededRng::new(params.seed);
+    let mut grid = match params.style {
+        MapStyle::Rooms => rooms_and_corridors(&mut rng, params.width, params.height),
+        MapStyle::Symmetric => symmetric_cover(&mut rng, params.width, params.height),
+    };
+    grid.wall_border();
+    grid.seal_pockets();
+
+    MapAsset {
+        tileSize: params.tile_size,
+        top: 0,
+        left: 0,
+        bottom: params.height,
+        right: params.width,
+        walls: merge_tiles(&grid.wall_tiles()),
+        floor: None,
+        decorations: vec![],
+    }
+}
+
+const CORRIDOR_WIDTH: i32 = 2;
+
+struct Room {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Room {
+    fn center(&self) -> (i32, i32) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+
+    /// Rooms must keep a tile of rock between them so they stay distinct.
+    fn overlaps(&self, other: &Room) -> bool {
+        self.x - 1 < other.x + other.width
+            && other.x - 1 < self.x + self.width
+            && self.y - 1 < other.y + other.height
+            && other.y - 1 < self.y + self.height
+    }
+}
+
+fn rooms_and_corridors(rng: &mut SeededRng, width: i32, height: i32) -> Grid {
+    let mut grid = Grid::new(width, height, true);
+    let max_room_width = (width / 2).clamp(3, 8);
+    let max_room_height = (height / 2).clamp(3, 8);
+    let target_rooms = ((width * height) / 60).max(2);
+
+    let mut rooms: Vec<Room> = Vec::new();
+    for _ in 0..target_rooms * 10 {
+        if rooms.len() as i32 >= target_rooms {
+            break;
+        }
+
+        let room_width = rng.range(3, max_room_width + 1);
+        let room_height = rng.range(3, max_room_height + 1);
+        let room = Room {
+            x: rng.range(1, width - room_width),
+            y: rng.range(1, height - room_height),
+            width: room_width,
+            height: room_height,
+        };
+
+        if rooms.iter().all(|other| !room.overlaps(other)) {
+            rooms.push(room);
+        }
+    }
+
+    for room in &rooms {
+        grid.fill_rect(room.x, room.y, room.width, room.height, false);
+    }
+
+    // chaining each room to the previous one connects all of them
+    for pair in rooms.windows(2) {
+        let (x1, y1) = pair[0].center();
+        let (x2, y2) = pair[1].center();
+        let (corner_x, corner_y) = if rng.chance(1, 2) { (x2, y1) } else { (x1, y2) };
+        carve_corridor(&mut grid, (x1, y1), (corner_x, corner_y));
+        carve_corridor(&mut grid, (corner_x, corner_y), (x2, y2));
+    }
+
+    grid
+}
+
+fn carve_corridor(grid: &mut Grid, (x1, y1): (i32, i32), (x2, y2): (i32, i32)) {
+    let (left, right) = (x1.min(x2), x1.max(x2));
+    let (top, bottom) = (y1.min(y2), y1.max(y2));
+    grid.fill_rect(
+        left,
+        top,
+        right - left + CORRIDOR_WIDTH,
+        bottom - top + CORRIDOR_WIDTH,
+        false,
+    );
+}
+
+fn symmetric_cover(rng: &mut SeededRng, width: i32, height: i32) -> Grid {
+    let mut grid = Grid::new(width, height, false);
+
+    // cover is placed in the top-left quadrant and mirrored. The ring of
+    // tiles inside the outer wall is left open so the arena always has a path
+    // around it.
+    let quadrant_width = (width + 1) / 2;
+    let quadrant_height = (height + 1) / 2;
+    let pieces = ((quadrant_width * quadrant_height) / 25).max(1);
+
+    for _ in 0..pieces {
+        let (piece_width, piece_height) = match rng.range(0, 3) {
+            // horizontal bar
+            0 => (rng.range(2, 5), 1),
+            // vertical bar
+            1 => (1, rng.range(2, 5)),
+            // block
+            _ => (2, 2),
+        };
+        let x = rng.range(2, (quadrant_width - piece_width).max(3));
+        let y = rng.range(2, (quadrant_height - piece_height).max(3));
+
+        for dx in x..x + piece_width {
+            for dy in y..y + piece_height {
+                for (mx, my) in [
+                    (dx, dy),
+                    (width - 1 - dx, dy),
+                    (dx, height - 1 - dy),
+                    (width - 1 - dx, height - 1 - dy),
+                ] {
+                    if mx > 1 && mx < width - 2 && my > 1 && my < height - 2 {
+                        grid.set(mx, my, true);
+                    }
+                }
+            }
+        }
+    }
+
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    fn params(seed: u64, style: MapStyle) -> GeneratorParams {
+        GeneratorParams {
+            seed,
+            width: 17,
+            height: 33,
+            tile_size: 64,
+            style,
+        }
+    }
+
+    fn grid_of(map: &MapAsset) -> Grid {
+        let mut grid = Grid::new(map.right - map.left, map.bottom - map.top, false);
+        for (x, y) in map.wall_tiles() {
+            grid.set(x, y, true);
+        }
+        grid
+    }
+
+    /// Compares against the checked-in snapshot. Run with `UPDATE_SNAPSHOTS=1`
+    /// to rewrite it after changing the generator on purpose.
+    fn assert_snapshot(name: &str, map: &MapAsset) {
+        let path = format!("{}/src/snapshots/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+        let json = serde_json::to_string_pretty(map).expect("Serialization should work");
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&path, json).expect("Writing the snapshot should succeed");
+            return;
+        }
+        let snapshot = fs::read_to_string(&path).expect("Snapshot should exist");
+        assert!(
+            json == snapshot,
+            "{} no longer matches {}. Rerun with UPDATE_SNAPSHOTS=1 if that's intended",
+            name,
+            path
+        );
+    }
+
+    #[test]
+    fn maps_match_their_snapshots() {
+        assert_snapshot("rooms-seed-42", &generate(&params(42, MapStyle::Rooms)));
+        assert_snapshot(
+            "symmetric-seed-7",
+            &generate(&params(7, MapStyle::Symmetric)),
+        );
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_map() {
+        for style in [MapStyle::Rooms, MapStyle::Symmetric] {
+            let first = generate(&params(1234, style));
+            let second = generate(&params(1234, style));
+            assert_eq!(first.walls, second.walls);
+        }
+    }
+
+    #[test]
+    fn maps_are_walled_in() {
+        for style in [MapStyle::Rooms, MapStyle::Symmetric] {
+            for seed in 0..50 {
+                let grid = grid_of(&generate(&params(seed, style)));
+                for x in 0..grid.width {
+                    for y in [0, grid.height - 1] {
+                        assert!(
+                            grid.is_wall(x, y),
+                            "{:?} seed {} is open at {}, {}",
+                            style,
+                            seed,
+                            x,
+                            y
+                        );
+                    }
+                }
+                for y in 0..grid.height {
+                    for x in [0, grid.width - 1] {
+                        assert!(
+                            grid.is_wall(x, y),
+                            "{:?} seed {} is open at {}, {}",
+                            style,
+                            seed,
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_open_tile_is_reachable() {
+        for style in [MapStyle::Rooms, MapStyle::Symmetric] {
+            for seed in 0..50 {
+                let map = generate(&params(seed, style));
+                assert_eq!(
+                    grid_of(&map).open_regions().len(),
+                    1,
+                    "{:?} map for seed {} has unreachable areas",
+                    style,
+                    seed
+                );
+            }
+        }
+    }
+}
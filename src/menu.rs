@@ -0,0 +1,504 @@
+use bevy::{app::AppExit, prelude::*, window::ReceivedCharacter};
+
+use crate::{
+    connection::ConnectError,
+    copy_paste::GameClipboard,
+    join_link::JoinTarget,
+    loading::GameAssets,
+    names::{validate_nickname, Nickname, MAX_NICKNAME_LENGTH},
+    sound::{spawn_volume_sliders, VolumeSettings},
+    AppState,
+};
+
+/// Which room to connect to once the app enters `AppState::Connecting`.
+/// Without a room ID a new room is created.
+pub struct ConnectRequest {
+    pub room_id: Option<String>,
+    /// Overrides `--app-id` for rooms joined through a link to another app
+    pub app_id: Option<String>,
+}
+
+/// Why the last connection attempt failed, shown on the main menu.
+pub struct ConnectionError(pub ConnectError);
+
+/// What's typed into the main menu's text fields.
+#[derive(Default)]
+pub struct MenuFields {
+    pub room_id: String,
+    pub nickname: String,
+    /// The field typing goes into
+    pub focused: MenuField,
+}
+
+/// A text field on the main menu. Clicking one or pressing Tab focuses it.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MenuField {
+    #[default]
+    RoomId,
+    Nickname,
+}
+
+impl MenuField {
+    fn placeholder(&self) -> &'static str {
+        match self {
+            MenuField::RoomId => "Type or paste a room ID or link",
+            MenuField::Nickname => "Nickname (optional)",
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum MenuButton {
+    CreateRoom,
+    JoinRoom,
+    PasteRoomId,
+    RecentRooms,
+    Quit,
+    BackToMenu,
+}
+
+/// Root of a full-screen menu, despawned when its state is left.
+#[derive(Component)]
+pub struct MenuScreen;
+
+#[derive(Component)]
+pub struct MenuFieldText(MenuField);
+
+/// Says what's wrong with what's typed into a field, if anything.
+#[derive(Component)]
+pub struct MenuFieldHint(MenuField);
+
+#[derive(Component)]
+pub struct ConnectionStatusText;
+
+const BACKGROUND: Color = Color::rgb(0.1, 0.1, 0.12);
+pub const FIELD: Color = Color::rgb(0.2, 0.2, 0.24);
+const FOCUSED_FIELD: Color = Color::rgb(0.28, 0.28, 0.34);
+pub const NORMAL_BUTTON: Color = Color::rgb(0.25, 0.25, 0.3);
+pub const HOVERED_BUTTON: Color = Color::rgb(0.35, 0.35, 0.42);
+pub const PRESSED_BUTTON: Color = Color::rgb(0.45, 0.6, 0.45);
+pub const ERROR_TEXT: Color = Color::rgb(0.95, 0.4, 0.4);
+
+pub fn text_style(assets: &GameAssets, font_size: f32, color: Color) -> TextStyle {
+    TextStyle {
+        font: assets.font.clone(),
+        font_size,
+        color,
+    }
+}
+
+pub fn spawn_screen(commands: &mut Commands, spawn_children: impl FnOnce(&mut ChildBuilder)) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: BACKGROUND.into(),
+            ..default()
+        })
+        .insert(MenuScreen)
+        .with_children(spawn_children);
+}
+
+pub fn spawn_button(
+    parent: &mut ChildBuilder,
+    assets: &GameAssets,
+    label: &str,
+    button: impl Component,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(300.0), Val::Px(50.0)),
+                margin: UiRect::all(Val::Px(8.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: NORMAL_BUTTON.into(),
+            ..default()
+        })
+        .insert(button)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                label,
+                text_style(assets, 24.0, Color::WHITE),
+            ));
+        });
+}
+
+pub fn spawn_title(parent: &mut ChildBuilder, assets: &GameAssets, title: &str) {
+    parent.spawn_bundle(
+        TextBundle::from_section(title, text_style(assets, 40.0, Color::WHITE)).with_style(Style {
+            margin: UiRect::all(Val::Px(20.0)),
+            ..default()
+        }),
+    );
+}
+
+pub fn spawn_main_menu(
+    assets: Res<GameAssets>,
+    connection_error: Option<Res<ConnectionError>>,
+    fields: Res<MenuFields>,
+    volume: Res<VolumeSettings>,
+    mut commands: Commands,
+) {
+    spawn_screen(&mut commands, |parent| {
+        spawn_title(parent, &assets, "Topdown Shooter");
+
+        if let Some(error) = connection_error {
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    format!("Couldn't connect: {}", error.0),
+                    text_style(&assets, 18.0, ERROR_TEXT),
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(10.0)),
+                    max_size: Size::new(Val::Px(600.0), Val::Undefined),
+                    ..default()
+                }),
+            );
+        }
+
+        spawn_field_row(parent, |parent| {
+            spawn_field(parent, &assets, &fields, MenuField::Nickname, 300.0);
+        });
+        spawn_field_hint(parent, &assets, &fields, MenuField::Nickname);
+        spawn_button(parent, &assets, "Create Room", MenuButton::CreateRoom);
+
+        spawn_field_row(parent, |parent| {
+            spawn_field(parent, &assets, &fields, MenuField::RoomId, 226.0);
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(66.0), Val::Percent(100.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButton::PasteRoomId)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        "Paste",
+                        text_style(&assets, 16.0, Color::WHITE),
+                    ));
+                });
+        });
+        spawn_field_hint(parent, &assets, &fields, MenuField::RoomId);
+        spawn_button(parent, &assets, "Join Room", MenuButton::JoinRoom);
+        spawn_button(parent, &assets, "Recent Rooms", MenuButton::RecentRooms);
+
+        spawn_button(parent, &assets, "Quit", MenuButton::Quit);
+        spawn_volume_sliders(parent, &assets, &volume);
+    });
+}
+
+fn spawn_field_row(parent: &mut ChildBuilder, spawn_children: impl FnOnce(&mut ChildBuilder)) {
+    parent
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(300.0), Val::Px(40.0)),
+                margin: UiRect::new(Val::Px(8.0), Val::Px(8.0), Val::Px(24.0), Val::Px(0.0)),
+                justify_content: JustifyContent::SpaceBetween,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(spawn_children);
+}
+
+fn spawn_field(
+    parent: &mut ChildBuilder,
+    assets: &GameAssets,
+    fields: &MenuFields,
+    field: MenuField,
+    width: f32,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(width), Val::Percent(100.0)),
+                padding: UiRect::all(Val::Px(8.0)),
+                align_items: AlignItems::Center,
+                overflow: Overflow::Hidden,
+                ..default()
+            },
+            color: field_color(fields, field).into(),
+            ..default()
+        })
+        .insert(field)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    field_label(fields, field),
+                    text_style(assets, 16.0, Color::WHITE),
+                ))
+                .insert(MenuFieldText(field));
+        });
+}
+
+fn spawn_field_hint(
+    parent: &mut ChildBuilder,
+    assets: &GameAssets,
+    fields: &MenuFields,
+    field: MenuField,
+) {
+    parent
+        .spawn_bundle(TextBundle::from_section(
+            field_hint(fields, field),
+            text_style(assets, 14.0, ERROR_TEXT),
+        ))
+        .insert(MenuFieldHint(field));
+}
+
+impl MenuFields {
+    fn value(&self, field: MenuField) -> &String {
+        match field {
+            MenuField::RoomId => &self.room_id,
+            MenuField::Nickname => &self.nickname,
+        }
+    }
+
+    fn value_mut(&mut self, field: MenuField) -> &mut String {
+        match field {
+            MenuField::RoomId => &mut self.room_id,
+            MenuField::Nickname => &mut self.nickname,
+        }
+    }
+}
+
+fn field_color(fields: &MenuFields, field: MenuField) -> Color {
+    if fields.focused == field {
+        FOCUSED_FIELD
+    } else {
+        FIELD
+    }
+}
+
+fn field_label(fields: &MenuFields, field: MenuField) -> String {
+    let value = fields.value(field);
+    if value.is_empty() {
+        field.placeholder().to_string()
+    } else if fields.focused == field {
+        format!("{}_", value)
+    } else {
+        value.clone()
+    }
+}
+
+fn field_hint(fields: &MenuFields, field: MenuField) -> String {
+    let value = fields.value(field);
+    if value.is_empty() {
+        return String::new();
+    }
+    let error = match field {
+        MenuField::RoomId => value.parse::<JoinTarget>().err().map(|e| e.to_string()),
+        MenuField::Nickname => validate_nickname(value).err(),
+    };
+    error.unwrap_or_default()
+}
+
+/// The nickname to log in with, or `None` to log in anonymously.
+fn chosen_nickname(field: &str) -> Result<Option<String>, String> {
+    if field.trim().is_empty() {
+        Ok(None)
+    } else {
+        validate_nickname(field).map(Some)
+    }
+}
+
+pub fn spawn_disconnected_screen(assets: Res<GameAssets>, mut commands: Commands) {
+    spawn_screen(&mut commands, |parent| {
+        spawn_title(parent, &assets, "Disconnected");
+        parent.spawn_bundle(TextBundle::from_section(
+            "The connection to the room was lost.",
+            text_style(&assets, 18.0, Color::WHITE),
+        ));
+        spawn_button(parent, &assets, "Back to Menu", MenuButton::BackToMenu);
+    });
+}
+
+pub fn spawn_connecting_screen(
+    assets: Res<GameAssets>,
+    request: Res<ConnectRequest>,
+    mut commands: Commands,
+) {
+    let message = match &request.room_id {
+        Some(room_id) => format!("Joining {}...", room_id),
+        None => "Creating a room...".to_string(),
+    };
+    spawn_screen(&mut commands, |parent| {
+        spawn_title(parent, &assets, &message);
+        parent
+            .spawn_bundle(TextBundle::from_section(
+                "",
+                text_style(&assets, 18.0, Color::WHITE),
+            ))
+            .insert(ConnectionStatusText);
+        spawn_button(parent, &assets, "Cancel", MenuButton::BackToMenu);
+    });
+}
+
+pub fn despawn_menu_screen(screen_query: Query<Entity, With<MenuScreen>>, mut commands: Commands) {
+    for entity in &screen_query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn edit_menu_fields(
+    mut received_characters: EventReader<ReceivedCharacter>,
+    keyboard: Res<Input<KeyCode>>,
+    button_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    field_query: Query<(&Interaction, &MenuField), Changed<Interaction>>,
+    clipboard: Res<GameClipboard>,
+    mut fields: ResMut<MenuFields>,
+    mut field_color_query: Query<(&MenuField, &mut UiColor)>,
+    mut text_query: Query<(&mut Text, &MenuFieldText), Without<MenuFieldHint>>,
+    mut hint_query: Query<(&mut Text, &MenuFieldHint), Without<MenuFieldText>>,
+) {
+    let mut focused = fields.focused;
+    for (interaction, field) in &field_query {
+        if *interaction == Interaction::Clicked {
+            focused = *field;
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Tab) {
+        focused = match focused {
+            MenuField::RoomId => MenuField::Nickname,
+            MenuField::Nickname => MenuField::RoomId,
+        };
+    }
+
+    let mut value = fields.value(focused).clone();
+    for event in received_characters.iter() {
+        let allowed = match focused {
+            MenuField::RoomId => !event.char.is_whitespace(),
+            MenuField::Nickname => value.chars().count() < MAX_NICKNAME_LENGTH,
+        };
+        if allowed && !event.char.is_control() {
+            value.push(event.char);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Back) {
+        value.pop();
+    }
+
+    let ctrl = keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let paste_clicked = button_query.iter().any(|(interaction, button)| {
+        *interaction == Interaction::Clicked && *button == MenuButton::PasteRoomId
+    });
+    let mut pasted_room_id = None;
+    if paste_clicked || (ctrl && keyboard.just_pressed(KeyCode::V)) {
+        // pasting replaces the field, since it only ever holds one value
+        match clipboard.0.get() {
+            Ok(contents) if paste_clicked => pasted_room_id = Some(contents.trim().to_string()),
+            Ok(contents) => value = contents.trim().to_string(),
+            Err(e) => warn!("Couldn't read the clipboard. Error was {}", e),
+        }
+    }
+
+    if focused == fields.focused && value == *fields.value(focused) && pasted_room_id.is_none() {
+        return;
+    }
+    fields.focused = focused;
+    *fields.value_mut(focused) = value;
+    if let Some(room_id) = pasted_room_id {
+        fields.room_id = room_id;
+    }
+
+    for (field, mut color) in &mut field_color_query {
+        *color = field_color(&fields, *field).into();
+    }
+    for (mut text, field_text) in &mut text_query {
+        text.sections[0].value = field_label(&fields, field_text.0);
+    }
+    for (mut text, hint) in &mut hint_query {
+        text.sections[0].value = field_hint(&fields, hint.0);
+    }
+}
+
+pub fn menu_buttons(
+    mut interaction_query: Query<(&Interaction, &MenuButton, &mut UiColor), Changed<Interaction>>,
+    keyboard: Res<Input<KeyCode>>,
+    fields: Res<MenuFields>,
+    mut nickname: ResMut<Nickname>,
+    mut state: ResMut<State<AppState>>,
+    mut app_exit_events: EventWriter<AppExit>,
+    mut commands: Commands,
+) {
+    let mut clicked = None;
+    for (interaction, button, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Clicked => {
+                *color = PRESSED_BUTTON.into();
+                clicked = Some(*button);
+            }
+            Interaction::Hovered => *color = HOVERED_BUTTON.into(),
+            Interaction::None => *color = NORMAL_BUTTON.into(),
+        }
+    }
+
+    // Enter joins the typed room, the same as clicking Join Room
+    if clicked.is_none()
+        && keyboard.just_pressed(KeyCode::Return)
+        && *state.current() == AppState::MainMenu
+    {
+        clicked = Some(MenuButton::JoinRoom);
+    }
+
+    if matches!(
+        clicked,
+        Some(MenuButton::CreateRoom | MenuButton::JoinRoom | MenuButton::RecentRooms)
+    ) {
+        // the hint under the field already says what's wrong with a nickname
+        match chosen_nickname(&fields.nickname) {
+            Ok(chosen) if nickname.0 != chosen => nickname.0 = chosen,
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+
+    let next_state = match clicked {
+        Some(MenuButton::CreateRoom) => {
+            commands.insert_resource(ConnectRequest {
+                room_id: None,
+                app_id: None,
+            });
+            AppState::Connecting
+        }
+        Some(MenuButton::JoinRoom) => {
+            // the hint under the field already says why an ID can't be joined
+            let target = match fields.room_id.parse::<JoinTarget>() {
+                Ok(target) => target,
+                Err(_) => return,
+            };
+            commands.insert_resource(ConnectRequest {
+                room_id: Some(target.room_id),
+                app_id: target.app_id,
+            });
+            AppState::Connecting
+        }
+        // handled by edit_menu_fields
+        Some(MenuButton::PasteRoomId) => return,
+        Some(MenuButton::RecentRooms) => AppState::Lobby,
+        Some(MenuButton::BackToMenu) => AppState::MainMenu,
+        Some(MenuButton::Quit) => {
+            app_exit_events.send(AppExit);
+            return;
+        }
+        None => return,
+    };
+
+    commands.remove_resource::<ConnectionError>();
+    if let Err(e) = state.set(next_state) {
+        warn!("Couldn't switch to {:?}. Error was {:?}", next_state, e);
+    }
+}
@@ -0,0 +1,275 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{
+        camera::{ScalingMode, Viewport},
+        view::RenderLayers,
+    },
+};
+use clap::ValueEnum;
+
+use crate::{
+    components::{BulletId, CurrentPlayer, UserId},
+    layers,
+    loading::GameAssets,
+    palette::{player_color, LOCAL_PLAYER_INDICATOR},
+    serialization::{MapAsset, Wall},
+    AppState,
+};
+
+/// Minimap sprites live on their own render layer, so the main camera never
+/// draws them and the minimap camera draws nothing else.
+const MINIMAP_LAYER: u8 = 1;
+const MARGIN: f32 = 10.;
+const PLAYER_MARKER_SIZE: f32 = 96.;
+const BULLET_MARKER_SIZE: f32 = 40.;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MinimapRotation {
+    /// The map is fixed with its top edge up
+    NorthUp,
+    /// The map turns so the local player always aims up
+    PlayerUp,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MinimapCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+pub struct MinimapSettings {
+    /// Side length of the minimap in logical pixels
+    pub size: f32,
+    /// Opacity of the minimap background and walls, from 0 to 1
+    pub opacity: f32,
+    pub rotation: MinimapRotation,
+    pub corner: MinimapCorner,
+    pub show_bullets: bool,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        MinimapSettings {
+            size: 180.,
+            opacity: 0.6,
+            rotation: MinimapRotation::NorthUp,
+            corner: MinimapCorner::TopRight,
+            show_bullets: false,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MinimapCamera;
+
+#[derive(Component)]
+pub struct MinimapMarker;
+
+pub fn setup_minimap(mut commands: Commands) {
+    commands
+        .spawn_bundle(Camera2dBundle {
+            camera: Camera {
+                // render after the main camera, on top of it
+                priority: 1,
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::None,
+            },
+            ..default()
+        })
+        .insert(UiCameraConfig { show_ui: false })
+        .insert(RenderLayers::layer(MINIMAP_LAYER))
+        .insert(MinimapCamera);
+}
+
+pub fn draw_minimap(
+    assets: Res<GameAssets>,
+    map_assets: Res<Assets<MapAsset>>,
+    settings: Res<MinimapSettings>,
+    mut commands: Commands,
+) {
+    let map = match map_assets.get(&assets.map) {
+        Some(map) => map,
+        None => return,
+    };
+
+    let bounds = Wall {
+        x: map.left,
+        y: map.top,
+        width: map.right - map.left,
+        height: map.bottom - map.top,
+    };
+    spawn_rect(
+        &mut commands,
+        map,
+        &bounds,
+        Color::rgba(0.05, 0.05, 0.05, settings.opacity),
+        layers::FLOOR,
+    );
+    for wall in &map.walls {
+        spawn_rect(
+            &mut commands,
+            map,
+            wall,
+            Color::rgba(0.7, 0.7, 0.7, settings.opacity),
+            layers::WALL,
+        );
+    }
+}
+
+fn spawn_rect(commands: &mut Commands, map: &MapAsset, rect: &Wall, color: Color, z: f32) {
+    let tile_size = map.tileSize as f32;
+    let size = Vec2::new(rect.width as f32, rect.height as f32) * tile_size;
+    let top_left = Vec2::new(rect.x as f32, -rect.y as f32) * tile_size;
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(size),
+                ..default()
+            },
+            transform: Transform::from_translation(
+                (top_left + Vec2::new(size.x, -size.y) / 2.).extend(z),
+            ),
+            ..default()
+        })
+        .insert(RenderLayers::layer(MINIMAP_LAYER));
+}
+
+pub fn add_minimap_markers(
+    player_query: Query<(Entity, &UserId, Option<&CurrentPlayer>), Added<UserId>>,
+    bullet_query: Query<Entity, Added<BulletId>>,
+    settings: Res<MinimapSettings>,
+    mut commands: Commands,
+) {
+    for (entity, user_id, current_player) in &player_query {
+        let color = if current_player.is_some() {
+            LOCAL_PLAYER_INDICATOR
+        } else {
+            player_color(user_id)
+        };
+        spawn_marker(&mut commands, entity, color, PLAYER_MARKER_SIZE);
+    }
+
+    if settings.show_bullets {
+        for entity in &bullet_query {
+            spawn_marker(&mut commands, entity, Color::YELLOW, BULLET_MARKER_SIZE);
+        }
+    }
+}
+
+fn spawn_marker(commands: &mut Commands, parent: Entity, color: Color, size: f32) {
+    commands.entity(parent).with_children(|parent| {
+        parent
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(size)),
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(RenderLayers::layer(MINIMAP_LAYER))
+            .insert(MinimapMarker);
+    });
+}
+
+pub fn update_minimap_camera(
+    windows: Res<Windows>,
+    state: Res<State<AppState>>,
+    settings: Res<MinimapSettings>,
+    assets: Res<GameAssets>,
+    map_assets: Res<Assets<MapAsset>>,
+    current_player_query: Query<&Transform, (With<CurrentPlayer>, Without<MinimapCamera>)>,
+    mut camera_query: Query<
+        (&mut Camera, &mut Transform, &mut OrthographicProjection),
+        With<MinimapCamera>,
+    >,
+) {
+    let (mut camera, mut camera_transform, mut projection) = camera_query.single_mut();
+
+    let map = map_assets.get(&assets.map);
+    // the menus cover the whole window, so the minimap only shows in a room
+    let active = settings.size > 0. && map.is_some() && *state.current() == AppState::InGame;
+    if camera.is_active != active {
+        camera.is_active = active;
+    }
+    let (window, map) = match (windows.get_primary(), map) {
+        (Some(window), Some(map)) if active => (window, map),
+        _ => return,
+    };
+
+    let viewport = minimap_viewport(window, &settings);
+    let viewport_changed = camera.viewport.as_ref().is_none_or(|current| {
+        current.physical_position != viewport.physical_position
+            || current.physical_size != viewport.physical_size
+    });
+    if viewport_changed {
+        camera.viewport = Some(viewport);
+        // projections are only recomputed when they change, not when the viewport does
+        projection.set_changed();
+    }
+
+    let tile_size = map.tileSize as f32;
+    let map_size =
+        Vec2::new((map.right - map.left) as f32, (map.bottom - map.top) as f32) * tile_size;
+    let map_center = Vec2::new(
+        (map.left + map.right) as f32,
+        -(map.top + map.bottom) as f32,
+    ) * tile_size
+        / 2.;
+
+    let player_transform = current_player_query.iter().next();
+    let (center, rotation, extent) = match (settings.rotation, player_transform) {
+        (MinimapRotation::PlayerUp, Some(player_transform)) => (
+            player_transform.translation.truncate(),
+            player_transform.rotation * Quat::from_rotation_z(-FRAC_PI_2),
+            // the map can be at any angle, so leave room for its longest side either way
+            Vec2::splat(map_size.max_element()),
+        ),
+        _ => (map_center, Quat::IDENTITY, map_size),
+    };
+
+    camera_transform.translation.x = center.x;
+    camera_transform.translation.y = center.y;
+    camera_transform.rotation = rotation;
+
+    let fits_extent = matches!(
+        projection.scaling_mode,
+        ScalingMode::Auto { min_width, min_height }
+            if min_width == extent.x && min_height == extent.y
+    );
+    if !fits_extent {
+        projection.scaling_mode = ScalingMode::Auto {
+            min_width: extent.x,
+            min_height: extent.y,
+        };
+    }
+}
+
+fn minimap_viewport(window: &Window, settings: &MinimapSettings) -> Viewport {
+    let scale_factor = window.scale_factor() as f32;
+    let size = ((settings.size * scale_factor) as u32).max(1);
+    let margin = (MARGIN * scale_factor) as u32;
+    let right = window.physical_width().saturating_sub(size + margin);
+    let bottom = window.physical_height().saturating_sub(size + margin);
+
+    let position = match settings.corner {
+        MinimapCorner::TopLeft => UVec2::new(margin, margin),
+        MinimapCorner::TopRight => UVec2::new(right, margin),
+        MinimapCorner::BottomLeft => UVec2::new(margin, bottom),
+        MinimapCorner::BottomRight => UVec2::new(right, bottom),
+    };
+
+    Viewport {
+        physical_position: position,
+        physical_size: UVec2::splat(size),
+        ..default()
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    components::{InGameEntity, UserId},
+    layers,
+    loading::GameAssets,
+    menu::text_style,
+    palette::player_color,
+};
+
+pub const MAX_NICKNAME_LENGTH: usize = 16;
+
+/// How far above a player's center their name is drawn.
+const NAME_TAG_OFFSET: f32 = 40.;
+
+const ADJECTIVES: [&str; 16] = [
+    "Swift", "Quiet", "Brave", "Lucky", "Rusty", "Bold", "Sly", "Grim", "Keen", "Wild", "Calm",
+    "Fuzzy", "Sharp", "Shady", "Jolly", "Stony",
+];
+const ANIMALS: [&str; 16] = [
+    "Otter", "Falcon", "Badger", "Lynx", "Heron", "Moose", "Gecko", "Raven", "Bison", "Viper",
+    "Panda", "Shrew", "Crane", "Hyena", "Koala", "Mole",
+];
+
+/// The nickname to log in with. Without one the login is anonymous.
+pub struct Nickname(pub Option<String>);
+
+/// Names of the players seen so far. Only names from nickname logins are
+/// known; everyone else gets a name derived from their ID.
+#[derive(Default)]
+pub struct PlayerNames(HashMap<UserId, String>);
+
+impl PlayerNames {
+    pub fn get(&self, user_id: &UserId) -> String {
+        self.0
+            .get(user_id)
+            .cloned()
+            .unwrap_or_else(|| fallback_name(user_id))
+    }
+
+    pub fn insert(&mut self, user_id: UserId, name: String) {
+        self.0.insert(user_id, name);
+    }
+
+    pub fn contains(&self, user_id: &UserId, name: &str) -> bool {
+        self.0.get(user_id).is_some_and(|known| known == name)
+    }
+}
+
+/// A short name like "SwiftOtter42" that's the same for an ID every time.
+pub fn fallback_name(user_id: &UserId) -> String {
+    let hash = user_id.stable_hash() as usize;
+    format!(
+        "{}{}{}",
+        ADJECTIVES[hash % ADJECTIVES.len()],
+        ANIMALS[(hash / ADJECTIVES.len()) % ANIMALS.len()],
+        (hash / (ADJECTIVES.len() * ANIMALS.len())) % 100
+    )
+}
+
+/// Trims a nickname and checks it's printable and not too long.
+pub fn validate_nickname(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("the nickname is empty".to_string());
+    }
+    if name.chars().count() > MAX_NICKNAME_LENGTH {
+        return Err(format!(
+            "nicknames are at most {} characters long",
+            MAX_NICKNAME_LENGTH
+        ));
+    }
+    match name.chars().find(|c| c.is_control()) {
+        Some(c) => Err(format!("nicknames can't contain {:?}", c)),
+        None => Ok(name.to_string()),
+    }
+}
+
+/// A player's name, drawn above them. It's its own entity rather than a child
+/// of the player so it doesn't turn with their aim.
+#[derive(Component)]
+pub struct NameTag {
+    pub player: Entity,
+}
+
+pub fn spawn_name_tags(
+    assets: Res<GameAssets>,
+    player_names: Res<PlayerNames>,
+    player_query: Query<(Entity, &UserId, &Transform), Added<UserId>>,
+    mut commands: Commands,
+) {
+    for (player, user_id, transform) in &player_query {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section(
+                    player_names.get(user_id),
+                    text_style(&assets, 16.0, player_color(user_id)),
+                )
+                .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_translation(name_tag_position(transform)),
+                ..default()
+            })
+            .insert(NameTag { player })
+            .insert(InGameEntity);
+    }
+}
+
+/// Keeps name tags above their players, hidden when they are, and removes
+/// them once their player is gone.
+pub fn update_name_tags(
+    player_names: Res<PlayerNames>,
+    player_query: Query<(&UserId, &Transform, &Visibility), Without<NameTag>>,
+    mut tag_query: Query<(Entity, &NameTag, &mut Text, &mut Transform, &mut Visibility)>,
+    mut commands: Commands,
+) {
+    for (entity, tag, mut text, mut transform, mut visibility) in &mut tag_query {
+        let (user_id, player_transform, player_visibility) = match player_query.get(tag.player) {
+            Ok(player) => player,
+            Err(_) => {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+
+        transform.translation = name_tag_position(player_transform);
+        if visibility.is_visible != player_visibility.is_visible {
+            visibility.is_visible = player_visibility.is_visible;
+        }
+        if player_names.is_changed() {
+            let name = player_names.get(user_id);
+            if text.sections[0].value != name {
+                text.sections[0].value = name;
+            }
+        }
+    }
+}
+
+fn name_tag_position(player_transform: &Transform) -> Vec3 {
+    player_transform
+        .translation
+        .truncate()
+        .extend(layers::NAME_TAG)
+        + Vec3::Y * NAME_TAG_OFFSET
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_hash_the_same_everywhere() {
+        // published FNV-1a test vectors
+        assert_eq!(UserId(String::new()).stable_hash(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(UserId("a".to_string()).stable_hash(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn fallback_names_are_pinned() {
+        let user_id = UserId("user-1".to_string());
+        assert_eq!(fallback_name(&user_id), "RustyHyena91");
+    }
+
+    #[test]
+    fn known_names_win_over_fallbacks() {
+        let mut names = PlayerNames::default();
+        let user_id = UserId("user-1".to_string());
+        names.insert(user_id.clone(), "Alice".to_string());
+        assert_eq!(names.get(&user_id), "Alice");
+        assert!(names.contains(&user_id, "Alice"));
+
+        let stranger = UserId("user-2".to_string());
+        assert_eq!(names.get(&stranger), fallback_name(&stranger));
+    }
+}
@@ -0,0 +1,102 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::components::{CurrentPlayer, UserId};
+
+/// The Okabe-Ito palette without black, chosen to stay distinguishable for
+/// the common kinds of color blindness.
+pub const PLAYER_COLORS: [Color; 7] = [
+    Color::rgb(0.902, 0.624, 0.0),
+    Color::rgb(0.337, 0.706, 0.914),
+    Color::rgb(0.0, 0.620, 0.451),
+    Color::rgb(0.941, 0.894, 0.259),
+    Color::rgb(0.0, 0.447, 0.698),
+    Color::rgb(0.835, 0.369, 0.0),
+    Color::rgb(0.8, 0.475, 0.655),
+];
+
+/// Marks the local player, drawn in white so it reads against any tint.
+pub const LOCAL_PLAYER_INDICATOR: Color = Color::WHITE;
+
+const RING_SIZE: u32 = 64;
+const RING_WIDTH: f32 = 4.;
+
+/// The same color for a player on every client and in every room.
+pub fn player_color(user_id: &UserId) -> Color {
+    PLAYER_COLORS[(user_id.stable_hash() % PLAYER_COLORS.len() as u64) as usize]
+}
+
+/// A ring drawn under the local player.
+pub struct LocalPlayerRing(pub Handle<Image>);
+
+pub fn create_local_player_ring(mut images: ResMut<Assets<Image>>, mut commands: Commands) {
+    let center = RING_SIZE as f32 / 2.;
+    let radius = center - RING_WIDTH / 2. - 1.;
+    let mut data = Vec::with_capacity((RING_SIZE * RING_SIZE * 4) as usize);
+    for y in 0..RING_SIZE {
+        for x in 0..RING_SIZE {
+            let distance = Vec2::new(x as f32 + 0.5 - center, y as f32 + 0.5 - center).length();
+            // fade out over a pixel at both edges to keep the ring smooth
+            let coverage = (RING_WIDTH / 2. + 0.5 - (distance - radius).abs()).clamp(0., 1.);
+            data.extend_from_slice(&[255, 255, 255, (coverage * 255.) as u8]);
+        }
+    }
+
+    let image = Image::new(
+        Extent3d {
+            width: RING_SIZE,
+            height: RING_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    commands.insert_resource(LocalPlayerRing(images.add(image)));
+}
+
+pub fn add_local_player_indicator(
+    ring: Res<LocalPlayerRing>,
+    player_query: Query<Entity, Added<CurrentPlayer>>,
+    mut commands: Commands,
+) {
+    for player in &player_query {
+        commands.entity(player).with_children(|parent| {
+            parent.spawn_bundle(SpriteBundle {
+                texture: ring.0.clone(),
+                sprite: Sprite {
+                    color: LOCAL_PLAYER_INDICATOR,
+                    ..default()
+                },
+                // just under the player's own sprite
+                transform: Transform::from_xyz(0., 0., -0.1),
+                ..default()
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_colors_are_pinned() {
+        assert_eq!(
+            player_color(&UserId("user-1".to_string())),
+            PLAYER_COLORS[6]
+        );
+    }
+
+    #[test]
+    fn ids_spread_over_the_whole_palette() {
+        let colors: Vec<Color> = (0..100)
+            .map(|i| player_color(&UserId(format!("user-{}", i))))
+            .collect();
+        for color in PLAYER_COLORS {
+            assert!(colors.contains(&color), "{:?} is never picked", color);
+        }
+    }
+}
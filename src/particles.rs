@@ -0,0 +1,406 @@
+use std::{collections::HashMap, f32::consts::PI, time::Duration};
+
+use bevy::prelude::*;
+use clap::ValueEnum;
+
+use crate::{
+    components::{BulletId, InGameEntity, UserId},
+    events::GameEvent,
+    layers,
+    loading::GameAssets,
+    mapgen::SeededRng,
+    serialization::MapAsset,
+};
+
+/// How close to a wall a bullet has to vanish to throw sparks, in pixels.
+const WALL_HIT_DISTANCE: f32 = 24.;
+const MUZZLE_FLASH_PARTICLES: usize = 8;
+const SPARK_PARTICLES: usize = 12;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParticleQuality {
+    Off,
+    Low,
+    High,
+}
+
+impl ParticleQuality {
+    /// The share of each effect's particles that are spawned.
+    fn density(&self) -> f32 {
+        match self {
+            ParticleQuality::Off => 0.,
+            ParticleQuality::Low => 0.4,
+            ParticleQuality::High => 1.,
+        }
+    }
+}
+
+pub struct ParticleSettings {
+    pub quality: ParticleQuality,
+    /// Most particles alive at once. Effects are cut short rather than going over
+    pub budget: usize,
+}
+
+pub struct ParticleRng(pub SeededRng);
+
+impl ParticleRng {
+    /// A value in `0..1`.
+    fn fraction(&mut self) -> f32 {
+        (self.0.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A unit vector at most `spread` radians either side of `direction`.
+    fn direction(&mut self, direction: Vec2, spread: f32) -> Vec2 {
+        let angle = direction.y.atan2(direction.x) + (self.fraction() * 2. - 1.) * spread;
+        Vec2::new(angle.cos(), angle.sin())
+    }
+}
+
+#[derive(Component)]
+pub struct Particle {
+    velocity: Vec2,
+    /// Fraction of the velocity lost every second
+    drag: f32,
+    lifetime: Timer,
+    start_size: f32,
+    end_size: f32,
+    start_alpha: f32,
+}
+
+struct ParticleSpawn {
+    position: Vec3,
+    velocity: Vec2,
+    drag: f32,
+    lifetime: f32,
+    color: Color,
+    start_size: f32,
+    end_size: f32,
+}
+
+/// What's left of the particle budget this frame.
+struct ParticleBudget {
+    density: f32,
+    remaining: usize,
+}
+
+impl ParticleBudget {
+    /// A frame's budget, given how many particles are still alive.
+    fn new(settings: &ParticleSettings, alive: usize) -> Self {
+        ParticleBudget {
+            density: settings.quality.density(),
+            remaining: settings.budget.saturating_sub(alive),
+        }
+    }
+
+    /// How many of an effect's `count` particles fit the quality and budget.
+    /// Fractions are rounded at random, so single particles thin out too.
+    fn take(&mut self, count: usize, rng: &mut ParticleRng) -> usize {
+        let expected = count as f32 * self.density;
+        let count = expected as usize + usize::from(rng.fraction() < expected.fract());
+        let count = count.min(self.remaining);
+        self.remaining -= count;
+        count
+    }
+}
+
+struct Emitter<'w, 's, 'a> {
+    commands: Commands<'w, 's>,
+    budget: ParticleBudget,
+    rng: &'a mut ParticleRng,
+}
+
+impl<'w, 's, 'a> Emitter<'w, 's, 'a> {
+    fn take(&mut self, count: usize) -> usize {
+        self.budget.take(count, self.rng)
+    }
+
+    fn spawn(&mut self, spawn: ParticleSpawn) {
+        self.commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: spawn.color,
+                    custom_size: Some(Vec2::splat(spawn.start_size)),
+                    ..default()
+                },
+                transform: Transform::from_translation(spawn.position),
+                ..default()
+            })
+            .insert(Particle {
+                velocity: spawn.velocity,
+                drag: spawn.drag,
+                lifetime: Timer::new(Duration::from_secs_f32(spawn.lifetime), false),
+                start_size: spawn.start_size,
+                end_size: spawn.end_size,
+                start_alpha: spawn.color.a(),
+            })
+            .insert(InGameEntity);
+    }
+
+    fn muzzle_flash(&mut self, position: Vec2, direction: Vec2) {
+        for _ in 0..self.take(MUZZLE_FLASH_PARTICLES) {
+            let speed = 150. + self.rng.fraction() * 250.;
+            let velocity = self.rng.direction(direction, 0.4) * speed;
+            let color = Color::rgba(1.0, 0.75 + self.rng.fraction() * 0.25, 0.3, 0.9);
+            let lifetime = 0.1 + self.rng.fraction() * 0.08;
+            self.spawn(ParticleSpawn {
+                position: position.extend(layers::PARTICLE),
+                velocity,
+                drag: 8.,
+                lifetime,
+                color,
+                start_size: 7.,
+                end_size: 2.,
+            });
+        }
+    }
+
+    fn trail(&mut self, position: Vec2) {
+        if self.take(1) == 0 {
+            return;
+        }
+        self.spawn(ParticleSpawn {
+            position: position.extend(layers::BULLET_TRAIL),
+            velocity: Vec2::ZERO,
+            drag: 0.,
+            lifetime: 0.25,
+            color: Color::rgba(1.0, 0.9, 0.6, 0.5),
+            start_size: 8.,
+            end_size: 1.,
+        });
+    }
+
+    fn sparks(&mut self, position: Vec2, normal: Vec2) {
+        // away from the wall, or in every direction when it's not known which way that is
+        let spread = if normal == Vec2::ZERO { PI } else { PI / 2. };
+        let normal = if normal == Vec2::ZERO {
+            Vec2::X
+        } else {
+            normal
+        };
+        for _ in 0..self.take(SPARK_PARTICLES) {
+            let speed = 80. + self.rng.fraction() * 260.;
+            let velocity = self.rng.direction(normal, spread) * speed;
+            let lifetime = 0.2 + self.rng.fraction() * 0.25;
+            self.spawn(ParticleSpawn {
+                position: position.extend(layers::PARTICLE),
+                velocity,
+                drag: 4.,
+                lifetime,
+                color: Color::rgba(1.0, 0.85, 0.4, 1.0),
+                start_size: 4.,
+                end_size: 1.,
+            });
+        }
+    }
+}
+
+/// Which way is out of the wall nearest to `position`, if one is close enough
+/// for a bullet vanishing there to have hit it.
+fn wall_normal(map: &MapAsset, position: Vec2) -> Option<Vec2> {
+    let tile_size = map.tileSize as f32;
+    map.walls
+        .iter()
+        .map(|wall| {
+            let min = Vec2::new(wall.x as f32, -((wall.y + wall.height) as f32)) * tile_size;
+            let max = Vec2::new((wall.x + wall.width) as f32, -(wall.y as f32)) * tile_size;
+            let offset = position - position.clamp(min, max);
+            (offset.length(), offset.normalize_or_zero())
+        })
+        .filter(|(distance, _)| *distance < WALL_HIT_DISTANCE)
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, normal)| normal)
+}
+
+/// Muzzle flashes for new bullets, trails behind moving ones and sparks where
+/// they hit walls. Effects the local player couldn't see aren't spawned.
+pub fn emit_particles(
+    mut game_events: EventReader<GameEvent>,
+    settings: Res<ParticleSettings>,
+    mut rng: ResMut<ParticleRng>,
+    assets: Res<GameAssets>,
+    map_assets: Res<Assets<MapAsset>>,
+    particle_query: Query<(), With<Particle>>,
+    player_query: Query<(&UserId, &Transform, &Visibility)>,
+    moved_bullet_query: Query<(&Transform, &Visibility), (With<BulletId>, Changed<Transform>)>,
+    bullet_query: Query<(&BulletId, &Visibility)>,
+    commands: Commands,
+) {
+    if settings.quality == ParticleQuality::Off {
+        game_events.clear();
+        return;
+    }
+
+    let mut emitter = Emitter {
+        commands,
+        budget: ParticleBudget::new(&settings, particle_query.iter().count()),
+        rng: &mut rng,
+    };
+
+    let players: HashMap<&UserId, (Vec2, bool)> = player_query
+        .iter()
+        .map(|(user_id, transform, visibility)| {
+            (
+                user_id,
+                (transform.translation.truncate(), visibility.is_visible),
+            )
+        })
+        .collect();
+    let bullet_visible: HashMap<i32, bool> = bullet_query
+        .iter()
+        .map(|(bullet, visibility)| (bullet.0, visibility.is_visible))
+        .collect();
+    let map = map_assets.get(&assets.map);
+
+    for event in game_events.iter() {
+        match event {
+            GameEvent::BulletFired {
+                position,
+                owner: Some(owner),
+                ..
+            } => {
+                if let Some((shooter, true)) = players.get(owner) {
+                    emitter.muzzle_flash(*position, (*position - *shooter).normalize_or_zero());
+                }
+            }
+            GameEvent::BulletExpired {
+                bullet_id,
+                position,
+            } if bullet_visible.get(bullet_id) == Some(&true) => {
+                if let Some(normal) = map.and_then(|map| wall_normal(map, *position)) {
+                    emitter.sparks(*position, normal);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // bullets only move when an update arrives, so that's when trails are laid
+    for (transform, visibility) in &moved_bullet_query {
+        if visibility.is_visible {
+            emitter.trail(transform.translation.truncate());
+        }
+    }
+}
+
+pub fn update_particles(
+    time: Res<Time>,
+    mut particle_query: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite)>,
+    mut commands: Commands,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut particle, mut transform, mut sprite) in &mut particle_query {
+        particle.lifetime.tick(time.delta());
+        if particle.lifetime.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let drag = (1. - particle.drag * delta).max(0.);
+        particle.velocity *= drag;
+        transform.translation += (particle.velocity * delta).extend(0.);
+
+        let progress = particle.lifetime.percent();
+        let size = particle.start_size + (particle.end_size - particle.start_size) * progress;
+        sprite.custom_size = Some(Vec2::splat(size));
+        sprite.color.set_a(particle.start_alpha * (1. - progress));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> MapAsset {
+        // one 2x2 tile wall with its top left corner at tile (1, 1)
+        serde_json::from_str(
+            r#"{"tileSize": 64, "top": 0, "left": 0, "bottom": 10, "right": 10,
+                "walls": [{"x": 1, "y": 1, "width": 2, "height": 2}]}"#,
+        )
+        .expect("The test map should deserialize")
+    }
+
+    fn assert_normal(position: Vec2, expected: Vec2) {
+        let normal = wall_normal(&map(), position).expect("The wall should be close enough");
+        assert!(
+            normal.distance(expected) < 1e-5,
+            "{} at {}, expected {}",
+            normal,
+            position,
+            expected
+        );
+    }
+
+    #[test]
+    fn sparks_fly_away_from_the_side_that_was_hit() {
+        // the wall covers x 64..192 and y -192..-64 in world space
+        assert_normal(Vec2::new(128., -50.), Vec2::Y);
+        assert_normal(Vec2::new(128., -200.), -Vec2::Y);
+        assert_normal(Vec2::new(50., -128.), -Vec2::X);
+        assert_normal(Vec2::new(200., -128.), Vec2::X);
+    }
+
+    #[test]
+    fn corners_throw_sparks_diagonally() {
+        assert_normal(Vec2::new(54., -54.), Vec2::new(-1., 1.).normalize());
+        assert_normal(Vec2::new(202., -202.), Vec2::new(1., -1.).normalize());
+        // the corner is further than the side's distance
+        assert_eq!(wall_normal(&map(), Vec2::new(44., -44.)), None);
+    }
+
+    #[test]
+    fn bullets_far_from_or_inside_walls() {
+        assert_eq!(wall_normal(&map(), Vec2::new(128., -30.)), None);
+        assert_eq!(wall_normal(&map(), Vec2::new(500., -500.)), None);
+        // inside, which way is out isn't known
+        assert_eq!(
+            wall_normal(&map(), Vec2::new(128., -128.)),
+            Some(Vec2::ZERO)
+        );
+    }
+
+    fn settings(quality: ParticleQuality, budget: usize) -> ParticleSettings {
+        ParticleSettings { quality, budget }
+    }
+
+    #[test]
+    fn the_budget_runs_out_and_comes_back_next_frame() {
+        let mut rng = ParticleRng(SeededRng::new(1));
+        let settings = settings(ParticleQuality::High, 20);
+
+        let mut budget = ParticleBudget::new(&settings, 0);
+        assert_eq!(budget.take(12, &mut rng), 12);
+        assert_eq!(budget.take(12, &mut rng), 8);
+        assert_eq!(budget.take(1, &mut rng), 0);
+
+        // particles still alive count against the next frame's budget
+        let mut budget = ParticleBudget::new(&settings, 15);
+        assert_eq!(budget.take(12, &mut rng), 5);
+        let mut budget = ParticleBudget::new(&settings, 0);
+        assert_eq!(budget.take(12, &mut rng), 12);
+        assert_eq!(ParticleBudget::new(&settings, 50).remaining, 0);
+    }
+
+    #[test]
+    fn quality_scales_how_many_particles_spawn() {
+        let mut rng = ParticleRng(SeededRng::new(1));
+        let mut taken = |quality| {
+            let mut budget = ParticleBudget::new(&settings(quality, usize::MAX), 0);
+            (0..1000)
+                .map(|_| budget.take(SPARK_PARTICLES, &mut rng))
+                .sum::<usize>()
+        };
+        assert_eq!(taken(ParticleQuality::Off), 0);
+        assert_eq!(taken(ParticleQuality::High), 1000 * SPARK_PARTICLES);
+        // 40% of 12 is 4.8, so 4 or 5 each time and 4.8 on average
+        let low = taken(ParticleQuality::Low);
+        assert!((4700..=4900).contains(&low), "{}", low);
+    }
+
+    #[test]
+    fn single_particles_thin_out_at_low_quality() {
+        let mut rng = ParticleRng(SeededRng::new(3));
+        let mut budget = ParticleBudget::new(&settings(ParticleQuality::Low, usize::MAX), 0);
+        let trails: usize = (0..1000).map(|_| budget.take(1, &mut rng)).sum();
+        assert!((350..=450).contains(&trails), "{}", trails);
+    }
+}
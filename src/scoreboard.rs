@@ -0,0 +1,444 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    components::{InGameEntity, UserId},
+    events::{GameEvent, StatsReported},
+    loading::GameAssets,
+    names::PlayerNames,
+};
+
+const PANEL_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.8);
+const LOCAL_PLAYER_ROW: Color = Color::rgba(0.2, 0.9, 0.3, 0.25);
+const HEADER_TEXT: Color = Color::rgb(0.8, 0.8, 0.8);
+const COLUMN_WIDTHS: [f32; 4] = [280.0, 80.0, 80.0, 80.0];
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct PlayerStats {
+    pub kills: u32,
+    pub deaths: u32,
+    pub score: i32,
+}
+
+/// Everyone in the room, with their stats. These are derived from players and
+/// bullets vanishing unless the server sends them. There's no ping column, as
+/// neither the server nor the transport says how long messages take.
+#[derive(Clone, PartialEq, Default)]
+pub struct Scoreboard {
+    stats: HashMap<UserId, PlayerStats>,
+    /// Players whose stats came from the server and mustn't be derived
+    reported: HashSet<UserId>,
+}
+
+impl Scoreboard {
+    fn derive(&mut self, user_id: &UserId, change: impl FnOnce(&mut PlayerStats)) {
+        if self.reported.contains(user_id) {
+            return;
+        }
+        let stats = self.stats.entry(user_id.clone()).or_default();
+        change(stats);
+        stats.score = stats.kills as i32 - stats.deaths as i32;
+    }
+
+    /// Applies one state diff's worth of events. Players who drop out are
+    /// removed, unless they were shot, as that's how dying looks.
+    fn apply<'a>(&mut self, events: impl IntoIterator<Item = &'a GameEvent>) {
+        let mut left = vec![];
+        for event in events {
+            match event {
+                GameEvent::PlayerJoined { user_id, .. } => {
+                    self.stats.entry(user_id.clone()).or_default();
+                }
+                GameEvent::PlayerLeft { user_id } => left.push(user_id),
+                GameEvent::PlayerEliminated { victim, shooter } => {
+                    left.retain(|user_id| *user_id != victim);
+                    self.derive(victim, |stats| stats.deaths += 1);
+                    if let Some(shooter) = shooter.as_ref().filter(|shooter| *shooter != victim) {
+                        self.derive(shooter, |stats| stats.kills += 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for user_id in left {
+            self.stats.remove(user_id);
+            self.reported.remove(user_id);
+        }
+    }
+
+    fn report(&mut self, user_id: &UserId, stats: PlayerStats) {
+        self.stats.insert(user_id.clone(), stats);
+        self.reported.insert(user_id.clone());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortColumn {
+    Player,
+    Kills,
+    Deaths,
+    Score,
+}
+
+impl SortColumn {
+    const ALL: [SortColumn; 4] = [
+        SortColumn::Player,
+        SortColumn::Kills,
+        SortColumn::Deaths,
+        SortColumn::Score,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            SortColumn::Player => "Player",
+            SortColumn::Kills => "Kills",
+            SortColumn::Deaths => "Deaths",
+            SortColumn::Score => "Score",
+        }
+    }
+
+    fn compare(&self, a: (&str, &PlayerStats), b: (&str, &PlayerStats)) -> Ordering {
+        match self {
+            SortColumn::Player => a.0.to_lowercase().cmp(&b.0.to_lowercase()),
+            SortColumn::Kills => a.1.kills.cmp(&b.1.kills),
+            SortColumn::Deaths => a.1.deaths.cmp(&b.1.deaths),
+            SortColumn::Score => a.1.score.cmp(&b.1.score),
+        }
+    }
+}
+
+pub struct ScoreboardSort {
+    pub column: SortColumn,
+    pub descending: bool,
+}
+
+impl Default for ScoreboardSort {
+    fn default() -> Self {
+        ScoreboardSort {
+            column: SortColumn::Score,
+            descending: true,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ScoreboardPanel;
+
+#[derive(Component)]
+pub struct ScoreboardHeader(SortColumn);
+
+pub fn update_scoreboard(
+    mut game_events: EventReader<GameEvent>,
+    mut stats_events: EventReader<StatsReported>,
+    mut scoreboard: ResMut<Scoreboard>,
+) {
+    if game_events.is_empty() && stats_events.is_empty() {
+        return;
+    }
+    // worked out on a copy so the panel is only redrawn when a row changes
+    let mut updated = scoreboard.clone();
+    updated.apply(game_events.iter());
+    for StatsReported { user_id, stats } in stats_events.iter() {
+        updated.report(user_id, *stats);
+    }
+    if updated != *scoreboard {
+        *scoreboard = updated;
+    }
+}
+
+/// Shows the scoreboard while Tab is held. Clicking a column header sorts by
+/// it, and clicking it again flips the order.
+pub fn show_scoreboard(
+    keyboard: Res<Input<KeyCode>>,
+    mut mouse_button_input: ResMut<Input<MouseButton>>,
+    assets: Res<GameAssets>,
+    scoreboard: Res<Scoreboard>,
+    player_names: Res<PlayerNames>,
+    mut sort: ResMut<ScoreboardSort>,
+    client_user_id: Res<UserId>,
+    header_query: Query<(&Interaction, &ScoreboardHeader), Changed<Interaction>>,
+    panel_query: Query<Entity, With<ScoreboardPanel>>,
+    mut commands: Commands,
+) {
+    if !keyboard.pressed(KeyCode::Tab) {
+        for panel in &panel_query {
+            commands.entity(panel).despawn_recursive();
+        }
+        return;
+    }
+
+    for (interaction, header) in &header_query {
+        if *interaction == Interaction::Clicked {
+            // sorting isn't shooting
+            mouse_button_input.clear_just_pressed(MouseButton::Left);
+            if sort.column == header.0 {
+                sort.descending = !sort.descending;
+            } else {
+                sort.column = header.0;
+                sort.descending = header.0 != SortColumn::Player;
+            }
+        }
+    }
+
+    if !panel_query.is_empty()
+        && !scoreboard.is_changed()
+        && !sort.is_changed()
+        && !player_names.is_changed()
+    {
+        return;
+    }
+    for panel in &panel_query {
+        commands.entity(panel).despawn_recursive();
+    }
+
+    let mut rows: Vec<(&UserId, String, &PlayerStats)> = scoreboard
+        .stats
+        .iter()
+        .map(|(user_id, stats)| (user_id, player_names.get(user_id), stats))
+        .collect();
+    rows.sort_by(|a, b| {
+        let order = sort.column.compare((&a.1, a.2), (&b.1, b.2));
+        let order = if sort.descending {
+            order.reverse()
+        } else {
+            order
+        };
+        order.then_with(|| a.0 .0.cmp(&b.0 .0))
+    });
+
+    let text_style = |color| TextStyle {
+        font: assets.font.clone(),
+        font_size: 18.0,
+        color,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(ScoreboardPanel)
+        .insert(InGameEntity)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::ColumnReverse,
+                        padding: UiRect::all(Val::Px(12.0)),
+                        ..default()
+                    },
+                    color: PANEL_BACKGROUND.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            color: Color::NONE.into(),
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            for (column, width) in SortColumn::ALL.iter().zip(COLUMN_WIDTHS) {
+                                let arrow = match (sort.column == *column, sort.descending) {
+                                    (true, true) => " ↓",
+                                    (true, false) => " ↑",
+                                    (false, _) => "",
+                                };
+                                parent
+                                    .spawn_bundle(ButtonBundle {
+                                        style: cell_style(width),
+                                        color: Color::NONE.into(),
+                                        ..default()
+                                    })
+                                    .insert(ScoreboardHeader(*column))
+                                    .with_children(|parent| {
+                                        parent.spawn_bundle(TextBundle::from_section(
+                                            format!("{}{}", column.title(), arrow),
+                                            text_style(HEADER_TEXT),
+                                        ));
+                                    });
+                            }
+                        });
+
+                    for (user_id, name, stats) in rows {
+                        let background = if *user_id == *client_user_id {
+                            LOCAL_PLAYER_ROW
+                        } else {
+                            Color::NONE
+                        };
+                        let cells = [
+                            name,
+                            stats.kills.to_string(),
+                            stats.deaths.to_string(),
+                            stats.score.to_string(),
+                        ];
+                        parent
+                            .spawn_bundle(NodeBundle {
+                                color: background.into(),
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                for (cell, width) in cells.into_iter().zip(COLUMN_WIDTHS) {
+                                    parent
+                                        .spawn_bundle(NodeBundle {
+                                            style: cell_style(width),
+                                            color: Color::NONE.into(),
+                                            ..default()
+                                        })
+                                        .with_children(|parent| {
+                                            parent.spawn_bundle(TextBundle::from_section(
+                                                cell,
+                                                text_style(Color::WHITE),
+                                            ));
+                                        });
+                                }
+                            });
+                    }
+                });
+        });
+}
+
+fn cell_style(width: f32) -> Style {
+    Style {
+        size: Size::new(Val::Px(width), Val::Px(28.0)),
+        padding: UiRect::new(Val::Px(6.0), Val::Px(6.0), Val::Px(0.0), Val::Px(0.0)),
+        align_items: AlignItems::Center,
+        overflow: Overflow::Hidden,
+        ..default()
+    }
+}
+
+pub fn reset_scoreboard(mut scoreboard: ResMut<Scoreboard>) {
+    *scoreboard = Scoreboard::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str) -> UserId {
+        UserId(id.to_string())
+    }
+
+    fn joined(id: &str) -> GameEvent {
+        GameEvent::PlayerJoined {
+            user_id: user(id),
+            position: Vec2::ZERO,
+            aim_angle: 0.,
+            initial: false,
+        }
+    }
+
+    fn shot(victim: &str, shooter: &str) -> [GameEvent; 2] {
+        [
+            GameEvent::PlayerLeft {
+                user_id: user(victim),
+            },
+            GameEvent::PlayerEliminated {
+                victim: user(victim),
+                shooter: Some(user(shooter)),
+            },
+        ]
+    }
+
+    fn stats(kills: u32, deaths: u32) -> PlayerStats {
+        PlayerStats {
+            kills,
+            deaths,
+            score: kills as i32 - deaths as i32,
+        }
+    }
+
+    #[test]
+    fn eliminations_count_as_kills_and_deaths() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.apply(&[joined("a"), joined("b")]);
+        scoreboard.apply(&shot("b", "a"));
+        scoreboard.apply(&[joined("b")]);
+        scoreboard.apply(&shot("b", "a"));
+        scoreboard.apply(&shot("a", "b"));
+
+        assert_eq!(scoreboard.stats[&user("a")], stats(2, 1));
+        assert_eq!(scoreboard.stats[&user("b")], stats(1, 2));
+    }
+
+    #[test]
+    fn shooting_yourself_is_only_a_death() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.apply(&[joined("a")]);
+        scoreboard.apply(&shot("a", "a"));
+        assert_eq!(scoreboard.stats[&user("a")], stats(0, 1));
+    }
+
+    #[test]
+    fn players_who_leave_are_dropped() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.apply(&[joined("a"), joined("b")]);
+        scoreboard.report(&user("a"), stats(5, 0));
+        scoreboard.apply(&[GameEvent::PlayerLeft { user_id: user("a") }]);
+
+        assert_eq!(
+            scoreboard.stats.keys().collect::<Vec<_>>(),
+            vec![&user("b")]
+        );
+        assert!(scoreboard.reported.is_empty());
+    }
+
+    #[test]
+    fn reported_stats_replace_derived_ones() {
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.apply(&[joined("a"), joined("b")]);
+        scoreboard.report(&user("a"), stats(7, 3));
+        scoreboard.apply(&shot("b", "a"));
+
+        assert_eq!(scoreboard.stats[&user("a")], stats(7, 3));
+        assert_eq!(scoreboard.stats[&user("b")], stats(0, 1));
+    }
+
+    #[derive(Default)]
+    struct Redraws(usize);
+
+    fn count_redraws(scoreboard: Res<Scoreboard>, mut redraws: ResMut<Redraws>) {
+        if scoreboard.is_changed() {
+            redraws.0 += 1;
+        }
+    }
+
+    #[test]
+    fn the_board_only_changes_when_a_row_does() {
+        let mut app = App::new();
+        app.init_resource::<Scoreboard>()
+            .init_resource::<Redraws>()
+            .add_event::<GameEvent>()
+            .add_event::<StatsReported>()
+            .add_system(update_scoreboard)
+            .add_system(count_redraws.after(update_scoreboard));
+        app.world.send_event(joined("a"));
+        app.update();
+        assert_eq!(app.world.resource::<Redraws>().0, 1);
+
+        // a known player joining again and bullets vanishing change no rows
+        app.world.send_event(joined("a"));
+        app.world.send_event(GameEvent::BulletExpired {
+            bullet_id: 1,
+            position: Vec2::ZERO,
+        });
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<Redraws>().0, 1);
+
+        app.world.send_event(joined("b"));
+        app.update();
+        assert_eq!(app.world.resource::<Redraws>().0, 2);
+    }
+}
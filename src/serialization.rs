@@ -1,14 +1,24 @@
+use std::collections::HashSet;
+
 use bevy::{
     asset::{AssetLoader, LoadedAsset},
     reflect::TypeUuid,
 };
 use serde::{Deserialize, Serialize};
 
+// field names match the server's JSON
+#[allow(non_snake_case)]
 #[derive(Deserialize, Debug)]
 pub struct Player {
     pub id: String,
     pub position: Position,
     pub aimAngle: f32,
+    // not sent by the current server, the scoreboard uses them if it ever does
+    pub kills: Option<u32>,
+    pub deaths: Option<u32>,
+    pub score: Option<i32>,
+    /// Nickname from the player's login token, likewise unused so far
+    pub name: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -17,10 +27,13 @@ pub struct Position {
     pub y: f32,
 }
 
+#[allow(non_snake_case)]
 #[derive(Deserialize, Debug)]
 pub struct Bullet {
     pub id: i32,
     pub position: Position,
+    /// Who fired the bullet. Not sent by the current server
+    pub ownerId: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -29,6 +42,7 @@ pub struct GameState {
     pub bullets: Vec<Bullet>,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct UpdateMessage {
     #[serde(rename = "type")]
@@ -57,10 +71,35 @@ pub struct ClickInput {
     pub serialized_type: u64,
 }
 
+/// Chat messages use this type in both directions.
+pub const CHAT_MESSAGE_TYPE: u64 = 3;
+
+#[derive(Serialize)]
+pub struct ChatInput {
+    #[serde(rename = "type")]
+    pub serialized_type: u64,
+    pub message: String,
+}
+
+/// Just the type of a message from the server, to tell which struct to
+/// deserialize the rest into.
+#[derive(Deserialize, Debug)]
+pub struct MessageHeader {
+    #[serde(rename = "type")]
+    pub serialized_type: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatMessage {
+    /// User ID of whoever sent the message
+    pub sender: String,
+    pub message: String,
+}
+
 #[derive(Default)]
 pub struct MapLoader;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
 pub struct Wall {
     pub x: i32,
     pub y: i32,
@@ -68,7 +107,31 @@ pub struct Wall {
     pub height: i32,
 }
 
-#[derive(Deserialize, TypeUuid, Debug)]
+/// Whether a layer is drawn beneath players or over their heads.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerPlacement {
+    #[default]
+    Ground,
+    Overhead,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct TileLayer {
+    /// Asset path of the sprite drawn on every tile of the layer
+    pub texture: String,
+    #[serde(default)]
+    pub placement: LayerPlacement,
+    /// Explicit z, overriding the one implied by `placement`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub z: Option<f32>,
+    /// Rectangles covered by the layer. A floor with no tiles covers the whole map.
+    #[serde(default)]
+    pub tiles: Vec<Wall>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Serialize, TypeUuid, Clone, Debug)]
 #[uuid = "39cadc56-aa9c-4543-8640-a018b74b5052"]
 pub struct MapAsset {
     pub tileSize: i32,
@@ -77,6 +140,325 @@ pub struct MapAsset {
     pub bottom: i32,
     pub right: i32,
     pub walls: Vec<Wall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub floor: Option<TileLayer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub decorations: Vec<TileLayer>,
+}
+
+impl MapAsset {
+    /// Every tile covered by a wall, as `(x, y)` tile coordinates.
+    pub fn wall_tiles(&self) -> HashSet<(i32, i32)> {
+        let mut tiles = HashSet::new();
+        for wall in &self.walls {
+            for x in wall.x..wall.x + wall.width {
+                for y in wall.y..wall.y + wall.height {
+                    tiles.insert((x, y));
+                }
+            }
+        }
+        tiles
+    }
+
+    /// The rectangles the floor is drawn on.
+    pub fn floor_tiles(&self) -> Vec<Wall> {
+        match &self.floor {
+            Some(floor) if !floor.tiles.is_empty() => floor.tiles.clone(),
+            Some(_) => vec![Wall {
+                x: self.left,
+                y: self.top,
+                width: self.right - self.left,
+                height: self.bottom - self.top,
+            }],
+            None => vec![],
+        }
+    }
+}
+
+/// Merges individual wall tiles into the fewest rectangles that cover them
+/// without overlapping.
+///
+/// Cutting along a line that joins two concave corners resolves both corners
+/// at once, so the largest set of such lines that don't cross is cut first
+/// (found by matching horizontal lines against the vertical ones they cross).
+/// Every concave corner left over then gets one vertical cut running until it
+/// meets a wall edge or an earlier cut. The pieces this leaves are rectangles,
+/// and no partition has fewer.
+pub fn merge_tiles(tiles: &HashSet<(i32, i32)>) -> Vec<Wall> {
+    let corners = concave_corners(tiles);
+    let (horizontal, vertical) = corner_chords(tiles, &corners);
+
+    let mut cuts = HashSet::new();
+    for chord in independent_chords(&horizontal, &vertical) {
+        cuts.extend(chord.edges());
+    }
+    for corner in &corners {
+        if !touches_cut(&cuts, corner.point) {
+            cut_from(tiles, &mut cuts, corner.point, corner.vertical);
+        }
+    }
+
+    pieces(tiles, &cuts)
+}
+
+/// A unit edge on the tile grid. `Horizontal((x, y))` runs from point `(x, y)`
+/// to `(x + 1, y)`, between tiles `(x, y - 1)` and `(x, y)`. `Vertical((x, y))`
+/// runs from `(x, y)` to `(x, y + 1)`, between tiles `(x - 1, y)` and `(x, y)`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    Horizontal((i32, i32)),
+    Vertical((i32, i32)),
+}
+
+impl Edge {
+    /// The edge leaving `point` one step towards `(dx, dy)`.
+    fn from(point: (i32, i32), (dx, dy): (i32, i32)) -> Edge {
+        let (x, y) = point;
+        match (dx, dy) {
+            (1, 0) => Edge::Horizontal((x, y)),
+            (-1, 0) => Edge::Horizontal((x - 1, y)),
+            (0, 1) => Edge::Vertical((x, y)),
+            _ => Edge::Vertical((x, y - 1)),
+        }
+    }
+
+    /// Whether there's wall on both sides, so cutting here splits a wall.
+    fn is_inside(self, tiles: &HashSet<(i32, i32)>) -> bool {
+        let (a, b) = match self {
+            Edge::Horizontal((x, y)) => ((x, y - 1), (x, y)),
+            Edge::Vertical((x, y)) => ((x - 1, y), (x, y)),
+        };
+        tiles.contains(&a) && tiles.contains(&b)
+    }
+}
+
+/// A grid point where three of the four surrounding tiles are wall. `horizontal`
+/// and `vertical` are the directions in which its two boundary edges carry on
+/// into the wall.
+struct Corner {
+    point: (i32, i32),
+    horizontal: (i32, i32),
+    vertical: (i32, i32),
+}
+
+fn concave_corners(tiles: &HashSet<(i32, i32)>) -> Vec<Corner> {
+    let mut points: Vec<(i32, i32)> = tiles
+        .iter()
+        .flat_map(|&(x, y)| [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)])
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    points.sort_by_key(|&(x, y)| (y, x));
+
+    points
+        .into_iter()
+        .filter_map(|(x, y)| {
+            let around = [(x - 1, y - 1), (x, y - 1), (x - 1, y), (x, y)];
+            let mut missing = around.iter().filter(|tile| !tiles.contains(tile));
+            let &(mx, my) = missing.next()?;
+            if missing.next().is_some() {
+                return None;
+            }
+            Some(Corner {
+                point: (x, y),
+                horizontal: (if mx == x { -1 } else { 1 }, 0),
+                vertical: (0, if my == y { -1 } else { 1 }),
+            })
+        })
+        .collect()
+}
+
+/// A straight line through the wall joining two concave corners.
+struct Chord {
+    start: (i32, i32),
+    end: (i32, i32),
+}
+
+impl Chord {
+    fn edges(&self) -> Vec<Edge> {
+        let step = (
+            (self.end.0 - self.start.0).signum(),
+            (self.end.1 - self.start.1).signum(),
+        );
+        let mut point = self.start;
+        let mut edges = Vec::new();
+        while point != self.end {
+            edges.push(Edge::from(point, step));
+            point = (point.0 + step.0, point.1 + step.1);
+        }
+        edges
+    }
+
+    /// Whether a horizontal chord and a vertical one cross or share a corner.
+    fn crosses(&self, vertical: &Chord) -> bool {
+        let x = vertical.start.0;
+        let y = self.start.1;
+        (self.start.0..=self.end.0).contains(&x) && (vertical.start.1..=vertical.end.1).contains(&y)
+    }
+}
+
+/// Every horizontal and vertical chord, each running right or down from its
+/// start corner.
+fn corner_chords(tiles: &HashSet<(i32, i32)>, corners: &[Corner]) -> (Vec<Chord>, Vec<Chord>) {
+    let mut horizontal = Vec::new();
+    let mut vertical = Vec::new();
+    for corner in corners {
+        for (direction, chords) in [
+            (corner.horizontal, &mut horizontal),
+            (corner.vertical, &mut vertical),
+        ] {
+            if direction.0 < 0 || direction.1 < 0 {
+                continue;
+            }
+            let mut point = corner.point;
+            while Edge::from(point, direction).is_inside(tiles) {
+                point = (point.0 + direction.0, point.1 + direction.1);
+                let back = (-direction.0, -direction.1);
+                let meets_corner = corners.iter().any(|other| {
+                    other.point == point && (other.horizontal == back || other.vertical == back)
+                });
+                if meets_corner {
+                    chords.push(Chord {
+                        start: corner.point,
+                        end: point,
+                    });
+                    break;
+                }
+            }
+        }
+    }
+    (horizontal, vertical)
+}
+
+/// The largest set of chords where no horizontal chord crosses a vertical one.
+/// By König's theorem this is everything outside a minimum vertex cover of the
+/// crossing graph, which falls out of a maximum matching.
+fn independent_chords<'a>(horizontal: &'a [Chord], vertical: &'a [Chord]) -> Vec<&'a Chord> {
+    let crossing: Vec<Vec<usize>> = horizontal
+        .iter()
+        .map(|h| {
+            (0..vertical.len())
+                .filter(|&v| h.crosses(&vertical[v]))
+                .collect()
+        })
+        .collect();
+
+    fn augment(
+        h: usize,
+        crossing: &[Vec<usize>],
+        matched: &mut [Option<usize>],
+        seen: &mut [bool],
+    ) -> bool {
+        for &v in &crossing[h] {
+            if seen[v] {
+                continue;
+            }
+            seen[v] = true;
+            if matched[v].is_none_or(|other| augment(other, crossing, matched, seen)) {
+                matched[v] = Some(h);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut matched: Vec<Option<usize>> = vec![None; vertical.len()];
+    let is_matched: Vec<bool> = (0..horizontal.len())
+        .map(|h| augment(h, &crossing, &mut matched, &mut vec![false; vertical.len()]))
+        .collect();
+
+    // Follow alternating paths out of unmatched horizontal chords.
+    let mut reached_h = vec![false; horizontal.len()];
+    let mut reached_v = vec![false; vertical.len()];
+    let mut stack: Vec<usize> = (0..horizontal.len()).filter(|&h| !is_matched[h]).collect();
+    for &h in &stack {
+        reached_h[h] = true;
+    }
+    while let Some(h) = stack.pop() {
+        for &v in &crossing[h] {
+            if reached_v[v] {
+                continue;
+            }
+            reached_v[v] = true;
+            if let Some(next) = matched[v] {
+                if !reached_h[next] {
+                    reached_h[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+    }
+
+    let kept_h = horizontal.iter().zip(reached_h).filter(|(_, r)| *r);
+    let kept_v = vertical.iter().zip(reached_v).filter(|(_, r)| !*r);
+    kept_h.chain(kept_v).map(|(chord, _)| chord).collect()
+}
+
+fn touches_cut(cuts: &HashSet<Edge>, point: (i32, i32)) -> bool {
+    [(1, 0), (-1, 0), (0, 1), (0, -1)]
+        .into_iter()
+        .any(|step| cuts.contains(&Edge::from(point, step)))
+}
+
+/// Cuts from `point` towards `step` until reaching a wall edge or another cut.
+fn cut_from(
+    tiles: &HashSet<(i32, i32)>,
+    cuts: &mut HashSet<Edge>,
+    mut point: (i32, i32),
+    step: (i32, i32),
+) {
+    loop {
+        let edge = Edge::from(point, step);
+        if !edge.is_inside(tiles) {
+            return;
+        }
+        point = (point.0 + step.0, point.1 + step.1);
+        let meets_cut = touches_cut(cuts, point);
+        cuts.insert(edge);
+        if meets_cut {
+            return;
+        }
+    }
+}
+
+/// The rectangles left once the tiles are split along `cuts`, top to bottom.
+fn pieces(tiles: &HashSet<(i32, i32)>, cuts: &HashSet<Edge>) -> Vec<Wall> {
+    let mut ordered: Vec<(i32, i32)> = tiles.iter().copied().collect();
+    ordered.sort_by_key(|&(x, y)| (y, x));
+
+    let mut seen = HashSet::new();
+    let mut walls = Vec::new();
+    for start in ordered {
+        if !seen.insert(start) {
+            continue;
+        }
+        let (mut left, mut top, mut right, mut bottom) = (start.0, start.1, start.0, start.1);
+        let mut stack = vec![start];
+        while let Some((x, y)) = stack.pop() {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+            let neighbours = [
+                ((x + 1, y), Edge::Vertical((x + 1, y))),
+                ((x - 1, y), Edge::Vertical((x, y))),
+                ((x, y + 1), Edge::Horizontal((x, y + 1))),
+                ((x, y - 1), Edge::Horizontal((x, y))),
+            ];
+            for (tile, edge) in neighbours {
+                if tiles.contains(&tile) && !cuts.contains(&edge) && seen.insert(tile) {
+                    stack.push(tile);
+                }
+            }
+        }
+        walls.push(Wall {
+            x: left,
+            y: top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        });
+    }
+    walls
 }
 
 impl AssetLoader for MapLoader {
@@ -96,3 +478,99 @@ impl AssetLoader for MapLoader {
         &["json"]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_with_walls(walls: Vec<Wall>) -> MapAsset {
+        MapAsset {
+            tileSize: 64,
+            top: 0,
+            left: 0,
+            bottom: 33,
+            right: 17,
+            walls,
+            floor: None,
+            decorations: vec![],
+        }
+    }
+
+    /// Merging must cover exactly the tiles it was given, with no rectangle
+    /// overlapping another.
+    fn assert_round_trips(tiles: &HashSet<(i32, i32)>) {
+        let walls = merge_tiles(tiles);
+        let area: i32 = walls.iter().map(|wall| wall.width * wall.height).sum();
+        assert_eq!(area as usize, tiles.len(), "walls overlap: {:?}", walls);
+        assert_eq!(&map_with_walls(walls).wall_tiles(), tiles);
+    }
+
+    #[test]
+    fn merging_the_bundled_map_keeps_its_tiles() {
+        let map: MapAsset = serde_json::from_str(include_str!("../assets/data/map.json"))
+            .expect("Bundled map should be valid");
+        let tiles = map.wall_tiles();
+        assert_round_trips(&tiles);
+        assert!(merge_tiles(&tiles).len() <= map.walls.len());
+    }
+
+    #[test]
+    fn merging_keeps_awkward_shapes() {
+        let l_shape: HashSet<_> = [(0, 0), (0, 1), (0, 2), (1, 2), (2, 2)].into();
+        let ring: HashSet<_> = (0..5)
+            .flat_map(|x| (0..5).map(move |y| (x, y)))
+            .filter(|&(x, y)| !(1..4).contains(&x) || !(1..4).contains(&y))
+            .collect();
+        let checkerboard: HashSet<_> = (-3..3)
+            .flat_map(|x| (-3..3).map(move |y| (x, y)))
+            .filter(|&(x, y)| (x + y) % 2 == 0)
+            .collect();
+        for tiles in [HashSet::new(), l_shape, ring, checkerboard] {
+            assert_round_trips(&tiles);
+        }
+    }
+
+    fn tiles_from(rows: &[&str]) -> HashSet<(i32, i32)> {
+        rows.iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '#')
+                    .map(move |(x, _)| (x as i32, y as i32))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merging_finds_the_fewest_walls() {
+        let cases: [(&[&str], usize); 6] = [
+            // Growing the first row's run downwards splits the bar in three.
+            (&[".#.", "###"], 2),
+            (&["##.", "###", "##."], 2),
+            (&[".#.", "###", ".#."], 3),
+            (&["###", "#.#", "###"], 4),
+            (&["#.#", "###", "#.#"], 3),
+            (&["##..", "####", "..##", "..##"], 2),
+        ];
+        for (rows, fewest) in cases {
+            let tiles = tiles_from(rows);
+            assert_round_trips(&tiles);
+            assert_eq!(merge_tiles(&tiles).len(), fewest, "{:?}", rows);
+        }
+    }
+
+    #[test]
+    fn merging_a_solid_block_gives_one_wall() {
+        let block: HashSet<_> = (2..6).flat_map(|x| (3..5).map(move |y| (x, y))).collect();
+        assert_eq!(
+            merge_tiles(&block),
+            vec![Wall {
+                x: 2,
+                y: 3,
+                width: 4,
+                height: 2
+            }]
+        );
+    }
+}
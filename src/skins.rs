@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use bevy::{asset::AssetIo, prelude::*};
+use futures_lite::future;
+
+use crate::{atlas::PlayerAnimation, loading::GameAssets};
+
+/// Lists the default file for every asset, relative to the assets directory.
+const DEFAULT_MANIFEST: &str = "manifest.json";
+/// Each directory in here is a skin pack, with a manifest of its own listing
+/// the files it replaces, relative to the pack.
+const SKINS_DIR: &str = "skins";
+const SKIN_MANIFEST: &str = "manifest.json";
+
+/// The assets the game refers to by name rather than by path.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AssetName {
+    Font,
+    Player,
+    Bullet,
+    Wall,
+    CopyIcon,
+    /// Only read at startup, so switching skins doesn't change the map
+    Map,
+    FireSound,
+    ImpactSound,
+    FootstepSound,
+    JoinSound,
+    LeaveSound,
+}
+
+impl AssetName {
+    pub const ALL: [AssetName; 11] = [
+        AssetName::Font,
+        AssetName::Player,
+        AssetName::Bullet,
+        AssetName::Wall,
+        AssetName::CopyIcon,
+        AssetName::Map,
+        AssetName::FireSound,
+        AssetName::ImpactSound,
+        AssetName::FootstepSound,
+        AssetName::JoinSound,
+        AssetName::LeaveSound,
+    ];
+
+    /// What the asset is called in manifests.
+    pub fn key(&self) -> &'static str {
+        match self {
+            AssetName::Font => "font",
+            AssetName::Player => "player",
+            AssetName::Bullet => "bullet",
+            AssetName::Wall => "wall",
+            AssetName::CopyIcon => "copy_icon",
+            AssetName::Map => "map",
+            AssetName::FireSound => "fire_sound",
+            AssetName::ImpactSound => "impact_sound",
+            AssetName::FootstepSound => "footstep_sound",
+            AssetName::JoinSound => "join_sound",
+            AssetName::LeaveSound => "leave_sound",
+        }
+    }
+
+    /// Whether the game does without the asset when no manifest lists it.
+    fn is_optional(&self) -> bool {
+        matches!(
+            self,
+            AssetName::FireSound
+                | AssetName::ImpactSound
+                | AssetName::FootstepSound
+                | AssetName::JoinSound
+                | AssetName::LeaveSound
+        )
+    }
+
+    fn from_key(key: &str) -> Option<AssetName> {
+        AssetName::ALL.into_iter().find(|name| name.key() == key)
+    }
+}
+
+/// Resolves asset names to files, from the default manifest with the chosen
+/// skin pack's files on top. Call `resolve` after changing `skin`.
+pub struct AssetRegistry {
+    /// The skin pack in use, `None` for the defaults
+    pub skin: Option<String>,
+    /// Every skin pack found, by name
+    pub skins: Vec<String>,
+    paths: HashMap<AssetName, String>,
+}
+
+impl AssetRegistry {
+    pub fn new(skin: Option<String>) -> Self {
+        AssetRegistry {
+            skin,
+            skins: vec![],
+            paths: HashMap::new(),
+        }
+    }
+
+    pub fn path(&self, name: AssetName) -> &str {
+        self.get(name).unwrap_or_default()
+    }
+
+    /// The file for `name`, if any manifest lists one.
+    pub fn get(&self, name: AssetName) -> Option<&str> {
+        self.paths.get(&name).map(String::as_str)
+    }
+
+    /// Reads the manifests and works out which file each asset comes from.
+    /// Files a skin pack lists but doesn't have fall back to the defaults.
+    pub fn resolve(&mut self, asset_io: &dyn AssetIo) {
+        self.skins = find_skins(asset_io);
+        self.paths.clear();
+
+        match read_manifest(asset_io, Path::new(DEFAULT_MANIFEST)) {
+            Ok(manifest) => self.paths.extend(manifest),
+            Err(e) => error!("Failed to read the asset manifest. Error was {}", e),
+        }
+        for name in AssetName::ALL {
+            if !self.paths.contains_key(&name) && !name.is_optional() {
+                error!("The asset manifest doesn't list \"{}\"", name.key());
+            }
+        }
+
+        let skin = match &self.skin {
+            Some(skin) if self.skins.contains(skin) => skin.clone(),
+            Some(skin) => {
+                warn!("There's no skin pack called {}, using the defaults", skin);
+                self.skin = None;
+                return;
+            }
+            None => return,
+        };
+        let skin_dir = Path::new(SKINS_DIR).join(&skin);
+        let manifest = match read_manifest(asset_io, &skin_dir.join(SKIN_MANIFEST)) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!(
+                    "Failed to read the manifest of skin pack {}, using the defaults. Error was {}",
+                    skin, e
+                );
+                return;
+            }
+        };
+        for (name, file) in manifest {
+            let path = skin_dir.join(&file);
+            if asset_io.is_file(&path) {
+                self.paths.insert(name, asset_path(&path));
+            } else {
+                warn!(
+                    "Skin pack {} is missing {} for \"{}\", using the default",
+                    skin,
+                    file,
+                    name.key()
+                );
+            }
+        }
+    }
+
+    /// The skin pack after the current one, going back to the defaults after
+    /// the last.
+    pub fn next_skin(&self) -> Option<String> {
+        let next = match &self.skin {
+            Some(skin) => self
+                .skins
+                .iter()
+                .position(|s| s == skin)
+                .map_or(0, |i| i + 1),
+            None => 0,
+        };
+        self.skins.get(next).cloned()
+    }
+}
+
+/// Asset paths always use forward slashes.
+fn asset_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn find_skins(asset_io: &dyn AssetIo) -> Vec<String> {
+    let mut skins: Vec<String> = match asset_io.read_directory(Path::new(SKINS_DIR)) {
+        Ok(entries) => entries
+            .filter(|path: &PathBuf| asset_io.is_file(&path.join(SKIN_MANIFEST)))
+            .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+            .collect(),
+        // no skins installed
+        Err(_) => vec![],
+    };
+    skins.sort();
+    skins
+}
+
+fn read_manifest(
+    asset_io: &dyn AssetIo,
+    path: &Path,
+) -> Result<HashMap<AssetName, String>, String> {
+    let bytes = future::block_on(asset_io.load_path(path)).map_err(|e| e.to_string())?;
+    let entries: HashMap<String, String> =
+        serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+
+    let mut manifest = HashMap::new();
+    for (key, file) in entries {
+        match AssetName::from_key(&key) {
+            Some(name) => {
+                manifest.insert(name, file);
+            }
+            None => warn!("Unknown asset \"{}\" in {}", key, path.display()),
+        }
+    }
+    Ok(manifest)
+}
+
+/// An image that comes from the skin, and changes along with it.
+#[derive(Component)]
+pub struct Skinned(pub AssetName);
+
+/// F7 switches to the next skin pack.
+pub fn cycle_skins(
+    keyboard: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut registry: ResMut<AssetRegistry>,
+    mut assets: ResMut<GameAssets>,
+) {
+    if !keyboard.just_pressed(KeyCode::F7) {
+        return;
+    }
+    registry.skin = registry.next_skin();
+    registry.resolve(asset_server.asset_io());
+    assets.reload_skin(&registry, &asset_server);
+    info!(
+        "Switched to skin {}",
+        registry.skin.as_deref().unwrap_or("default")
+    );
+}
+
+/// Swaps what's already on screen over to the skin in use when it changes.
+pub fn apply_skin(
+    registry: Res<AssetRegistry>,
+    assets: Res<GameAssets>,
+    mut image_query: Query<(&Skinned, Option<&mut Handle<Image>>, Option<&mut UiImage>)>,
+    mut player_query: Query<&mut PlayerAnimation>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !registry.is_changed() || registry.is_added() {
+        return;
+    }
+
+    for (skinned, image, ui_image) in &mut image_query {
+        let new_image = match assets.image(skinned.0) {
+            Some(new_image) => new_image,
+            None => continue,
+        };
+        if let Some(mut image) = image {
+            *image = new_image.clone();
+        }
+        if let Some(mut ui_image) = ui_image {
+            ui_image.0 = new_image;
+        }
+    }
+
+    for mut animation in &mut player_query {
+        animation.sheet = assets.player.clone();
+    }
+
+    // all text uses the one font
+    for mut text in &mut text_query {
+        for section in &mut text.sections {
+            section.style.font = assets.font.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bevy::asset::FileAssetIo;
+
+    use super::*;
+
+    /// An assets directory with two skin packs and a directory that isn't one.
+    fn fake_assets(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("topdown-shooter-skins-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        let files = [
+            (
+                "manifest.json",
+                r#"{"font": "font.ttf", "player": "player.atlas.json", "bullet": "bullet.png",
+                    "wall": "wall.png", "copy_icon": "copy.png", "map": "map.json"}"#,
+            ),
+            (
+                "skins/brick/manifest.json",
+                r#"{"wall": "brick.png", "bullet": "missing.png"}"#,
+            ),
+            ("skins/brick/brick.png", ""),
+            ("skins/neon/manifest.json", "{}"),
+            ("skins/unfinished/readme.txt", ""),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().expect("Test files should be in a directory"))
+                .expect("Creating the test assets should work");
+            fs::write(path, contents).expect("Creating the test assets should work");
+        }
+        root
+    }
+
+    fn resolved(name: &str, skin: Option<&str>) -> AssetRegistry {
+        let asset_io = FileAssetIo::new(fake_assets(name), false);
+        let mut registry = AssetRegistry::new(skin.map(str::to_string));
+        registry.resolve(&asset_io);
+        registry
+    }
+
+    #[test]
+    fn skin_packs_are_found_by_their_manifests() {
+        let registry = resolved("found", None);
+        assert_eq!(registry.skins, ["brick", "neon"]);
+        assert_eq!(registry.path(AssetName::Wall), "wall.png");
+        assert_eq!(registry.get(AssetName::FireSound), None);
+    }
+
+    #[test]
+    fn files_a_skin_lacks_fall_back_to_the_defaults() {
+        let registry = resolved("fallback", Some("brick"));
+        assert_eq!(registry.skin.as_deref(), Some("brick"));
+        assert_eq!(registry.path(AssetName::Wall), "skins/brick/brick.png");
+        assert_eq!(registry.path(AssetName::Bullet), "bullet.png");
+        assert_eq!(registry.path(AssetName::Player), "player.atlas.json");
+    }
+
+    #[test]
+    fn unknown_skins_use_the_defaults() {
+        let registry = resolved("unknown", Some("unfinished"));
+        assert_eq!(registry.skin, None);
+        assert_eq!(registry.path(AssetName::Wall), "wall.png");
+    }
+
+    #[test]
+    fn skins_cycle_back_to_the_defaults() {
+        let mut registry = resolved("cycle", None);
+        let mut seen = vec![];
+        for _ in 0..4 {
+            registry.skin = registry.next_skin();
+            seen.push(registry.skin.clone());
+        }
+        assert_eq!(
+            seen,
+            [
+                Some("brick".to_string()),
+                Some("neon".to_string()),
+                None,
+                Some("brick".to_string())
+            ]
+        );
+
+        let no_skins = AssetRegistry::new(None);
+        assert_eq!(no_skins.next_skin(), None);
+    }
+}
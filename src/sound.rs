@@ -0,0 +1,602 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    components::{CurrentPlayer, MainCamera, UserId},
+    config::config_path,
+    events::GameEvent,
+    loading::GameAssets,
+    menu::text_style,
+    skins::AssetName,
+};
+
+/// Sounds closer than this to the listener play at full volume, in pixels.
+const FULL_VOLUME_DISTANCE: f32 = 200.;
+/// Sounds further than this from the listener aren't heard at all.
+const HEARING_DISTANCE: f32 = 1200.;
+/// How far a player moves between footsteps, in pixels.
+const STEP_LENGTH: f32 = 70.;
+/// Anything further than this in one frame is a teleport, not a step.
+const MAX_STEP: f32 = 200.;
+/// How many sounds the null sink remembers.
+const NULL_SINK_HISTORY: usize = 100;
+const VOLUME_FILE: &str = "volume.json";
+const SLIDER_WIDTH: f32 = 100.0;
+const SLIDER_TRACK: Color = Color::rgb(0.25, 0.25, 0.3);
+const SLIDER_FILL: Color = Color::rgb(0.45, 0.6, 0.45);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SoundEffect {
+    Fire,
+    Impact,
+    Footstep,
+    Join,
+    Leave,
+}
+
+impl SoundEffect {
+    pub const ALL: [SoundEffect; 5] = [
+        SoundEffect::Fire,
+        SoundEffect::Impact,
+        SoundEffect::Footstep,
+        SoundEffect::Join,
+        SoundEffect::Leave,
+    ];
+
+    pub fn asset_name(&self) -> AssetName {
+        match self {
+            SoundEffect::Fire => AssetName::FireSound,
+            SoundEffect::Impact => AssetName::ImpactSound,
+            SoundEffect::Footstep => AssetName::FootstepSound,
+            SoundEffect::Join => AssetName::JoinSound,
+            SoundEffect::Leave => AssetName::LeaveSound,
+        }
+    }
+
+    /// How loud the effect is next to the listener, before the volume settings.
+    fn base_volume(&self) -> f32 {
+        match self {
+            SoundEffect::Fire => 0.6,
+            SoundEffect::Impact => 0.5,
+            SoundEffect::Footstep => 0.3,
+            SoundEffect::Join | SoundEffect::Leave => 0.8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PlayedSound {
+    pub effect: SoundEffect,
+    /// From 0 to 1, after distance and the volume settings
+    pub volume: f32,
+}
+
+/// Where sound effects go.
+pub trait SoundSink: Send + Sync {
+    fn play(&mut self, sound: PlayedSound);
+}
+
+pub struct SoundOutput(pub Box<dyn SoundSink>);
+
+/// Plays nothing, but remembers the last few sounds so that headless runs
+/// can check which would have played.
+#[derive(Default)]
+pub struct NullSink {
+    pub played: Arc<Mutex<VecDeque<PlayedSound>>>,
+}
+
+impl SoundSink for NullSink {
+    fn play(&mut self, sound: PlayedSound) {
+        debug!("Not playing {:?}", sound);
+        let mut played = self
+            .played
+            .lock()
+            .expect("Null sink lock shouldn't be poisoned");
+        played.push_back(sound);
+        if played.len() > NULL_SINK_HISTORY {
+            played.pop_front();
+        }
+    }
+}
+
+/// Sounds waiting for `output_bevy_audio` to play them.
+#[derive(Clone, Default)]
+pub struct BevyAudioQueue(pub Arc<Mutex<Vec<PlayedSound>>>);
+
+/// Plays sounds through Bevy's audio.
+pub struct BevyAudioSink(pub BevyAudioQueue);
+
+impl SoundSink for BevyAudioSink {
+    fn play(&mut self, sound: PlayedSound) {
+        self.0
+             .0
+            .lock()
+            .expect("Audio queue lock shouldn't be poisoned")
+            .push(sound);
+    }
+}
+
+pub fn output_bevy_audio(queue: Res<BevyAudioQueue>, assets: Res<GameAssets>, audio: Res<Audio>) {
+    let sounds: Vec<PlayedSound> = queue
+        .0
+        .lock()
+        .expect("Audio queue lock shouldn't be poisoned")
+        .drain(..)
+        .collect();
+    for sound in sounds {
+        // sounds are optional, and a skin doesn't have to have any
+        if let Some(source) = assets.sounds.get(&sound.effect) {
+            audio.play_with_settings(
+                source.clone(),
+                PlaybackSettings::ONCE.with_volume(sound.volume),
+            );
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct VolumeSettings {
+    pub master: f32,
+    pub effects: f32,
+    pub footsteps: f32,
+}
+
+impl Default for VolumeSettings {
+    fn default() -> Self {
+        VolumeSettings {
+            master: 0.8,
+            effects: 1.0,
+            footsteps: 0.6,
+        }
+    }
+}
+
+impl VolumeSettings {
+    /// The saved settings, or the defaults if there aren't any.
+    pub fn load() -> Self {
+        let path = match config_path(VOLUME_FILE) {
+            Some(path) => path,
+            None => return VolumeSettings::default(),
+        };
+        match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to read volume settings from {}. Error was {}",
+                    path.display(),
+                    e
+                );
+                VolumeSettings::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VolumeSettings::default(),
+            Err(e) => {
+                warn!(
+                    "Failed to read volume settings from {}. Error was {}",
+                    path.display(),
+                    e
+                );
+                VolumeSettings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match config_path(VOLUME_FILE) {
+            Some(path) => path,
+            None => {
+                warn!("Nowhere to save volume settings to");
+                return;
+            }
+        };
+        let json = serde_json::to_string_pretty(self).expect("Serialization should work");
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&path, json)),
+            None => fs::write(&path, json),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to save volume settings to {}. Error was {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    fn channel(&self, channel: VolumeChannel) -> f32 {
+        match channel {
+            VolumeChannel::Master => self.master,
+            VolumeChannel::Effects => self.effects,
+            VolumeChannel::Footsteps => self.footsteps,
+        }
+    }
+
+    fn channel_mut(&mut self, channel: VolumeChannel) -> &mut f32 {
+        match channel {
+            VolumeChannel::Master => &mut self.master,
+            VolumeChannel::Effects => &mut self.effects,
+            VolumeChannel::Footsteps => &mut self.footsteps,
+        }
+    }
+
+    /// How loud `effect` plays next to the listener.
+    fn volume(&self, effect: SoundEffect) -> f32 {
+        let channel = match effect {
+            SoundEffect::Footstep => self.footsteps,
+            _ => self.effects,
+        };
+        self.master * channel * effect.base_volume()
+    }
+}
+
+/// How much of a sound `distance` pixels away is heard.
+fn attenuation(distance: f32) -> f32 {
+    let falloff = (distance - FULL_VOLUME_DISTANCE) / (HEARING_DISTANCE - FULL_VOLUME_DISTANCE);
+    1. - falloff.clamp(0., 1.)
+}
+
+/// Plays sounds for what happened in the game, quieter the further away from
+/// the local player it was, or from the camera while spectating.
+pub fn play_sound_effects(
+    mut game_events: EventReader<GameEvent>,
+    volume: Res<VolumeSettings>,
+    mut output: ResMut<SoundOutput>,
+    local_player_query: Query<&Transform, With<CurrentPlayer>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    player_query: Query<(Entity, &UserId, &Transform)>,
+    mut walked: Local<HashMap<Entity, (Vec2, f32)>>,
+) {
+    let listener = match local_player_query
+        .iter()
+        .next()
+        .or_else(|| camera_query.iter().next())
+    {
+        Some(transform) => transform.translation.truncate(),
+        None => return,
+    };
+    let mut play = |effect: SoundEffect, position: Vec2| {
+        let volume = volume.volume(effect) * attenuation(position.distance(listener));
+        if volume > 0.01 {
+            output.0.play(PlayedSound { effect, volume });
+        }
+    };
+
+    for event in game_events.iter() {
+        match event {
+            GameEvent::BulletFired { position, .. } => play(SoundEffect::Fire, *position),
+            GameEvent::BulletExpired { position, .. } => play(SoundEffect::Impact, *position),
+            GameEvent::PlayerJoined {
+                position,
+                initial: false,
+                ..
+            } => play(SoundEffect::Join, *position),
+            GameEvent::PlayerLeft { user_id } => {
+                if let Some((_, _, transform)) =
+                    player_query.iter().find(|(_, id, _)| *id == user_id)
+                {
+                    play(SoundEffect::Leave, transform.translation.truncate());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut footsteps = HashMap::new();
+    for (entity, _, transform) in &player_query {
+        let position = transform.translation.truncate();
+        let (last_position, mut travelled) = walked.get(&entity).copied().unwrap_or((position, 0.));
+        let step = position.distance(last_position);
+        if step < MAX_STEP {
+            travelled += step;
+        }
+        if travelled >= STEP_LENGTH {
+            travelled = 0.;
+            play(SoundEffect::Footstep, position);
+        }
+        footsteps.insert(entity, (position, travelled));
+    }
+    // players who've gone are dropped along the way
+    *walked = footsteps;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VolumeChannel {
+    Master,
+    Effects,
+    Footsteps,
+}
+
+impl VolumeChannel {
+    const ALL: [VolumeChannel; 3] = [
+        VolumeChannel::Master,
+        VolumeChannel::Effects,
+        VolumeChannel::Footsteps,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            VolumeChannel::Master => "Volume",
+            VolumeChannel::Effects => "Effects",
+            VolumeChannel::Footsteps => "Steps",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct VolumeSlider(VolumeChannel);
+
+#[derive(Component)]
+pub struct VolumeFill(VolumeChannel);
+
+pub fn spawn_volume_sliders(
+    parent: &mut ChildBuilder,
+    assets: &GameAssets,
+    volume: &VolumeSettings,
+) {
+    parent
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(8.0)),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            for channel in VolumeChannel::ALL {
+                parent.spawn_bundle(
+                    TextBundle::from_section(
+                        channel.label(),
+                        text_style(assets, 16.0, Color::WHITE),
+                    )
+                    .with_style(Style {
+                        margin: UiRect::new(
+                            Val::Px(12.0),
+                            Val::Px(6.0),
+                            Val::Px(0.0),
+                            Val::Px(0.0),
+                        ),
+                        ..default()
+                    }),
+                );
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(SLIDER_WIDTH), Val::Px(14.0)),
+                            ..default()
+                        },
+                        color: SLIDER_TRACK.into(),
+                        ..default()
+                    })
+                    .insert(VolumeSlider(channel))
+                    .with_children(|parent| {
+                        parent
+                            .spawn_bundle(NodeBundle {
+                                style: Style {
+                                    size: Size::new(
+                                        Val::Percent(100.0 * volume.channel(channel)),
+                                        Val::Percent(100.0),
+                                    ),
+                                    ..default()
+                                },
+                                color: SLIDER_FILL.into(),
+                                ..default()
+                            })
+                            .insert(VolumeFill(channel));
+                    });
+            }
+        });
+}
+
+/// Dragging a slider sets its volume, which is saved when the mouse is let go.
+pub fn adjust_volume(
+    windows: Res<Windows>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    mut volume: ResMut<VolumeSettings>,
+    mut dragging: Local<Option<VolumeChannel>>,
+    slider_query: Query<(&VolumeSlider, &Interaction, &Node, &GlobalTransform)>,
+    mut fill_query: Query<(&VolumeFill, &mut Style)>,
+) {
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        *dragging = slider_query
+            .iter()
+            .find(|(_, interaction, _, _)| **interaction == Interaction::Clicked)
+            .map(|(slider, _, _, _)| slider.0);
+    }
+    let channel = match *dragging {
+        Some(channel) => channel,
+        None => return,
+    };
+
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    let slider = slider_query
+        .iter()
+        .find(|(slider, _, _, _)| slider.0 == channel);
+    if let (Some(cursor), Some((_, _, node, transform))) = (cursor, slider) {
+        let left = transform.translation().x - node.size.x / 2.;
+        let value = ((cursor.x - left) / node.size.x).clamp(0., 1.);
+        if volume.channel(channel) != value {
+            *volume.channel_mut(channel) = value;
+        }
+    }
+
+    if volume.is_changed() {
+        for (fill, mut style) in &mut fill_query {
+            style.size.width = Val::Percent(100.0 * volume.channel(fill.0));
+        }
+    }
+    if mouse_button_input.just_released(MouseButton::Left) {
+        *dragging = None;
+        volume.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: VolumeSettings = VolumeSettings {
+        master: 0.5,
+        effects: 0.8,
+        footsteps: 0.4,
+    };
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn sounds_fade_out_between_the_two_distances() {
+        assert_close(attenuation(0.), 1.);
+        assert_close(attenuation(FULL_VOLUME_DISTANCE), 1.);
+        assert_close(attenuation(700.), 0.5);
+        assert_close(attenuation(HEARING_DISTANCE), 0.);
+        assert_close(attenuation(5000.), 0.);
+    }
+
+    #[test]
+    fn footsteps_have_their_own_channel() {
+        assert_close(SETTINGS.volume(SoundEffect::Fire), 0.5 * 0.8 * 0.6);
+        assert_close(SETTINGS.volume(SoundEffect::Footstep), 0.5 * 0.4 * 0.3);
+        let muted = VolumeSettings {
+            master: 0.,
+            ..SETTINGS
+        };
+        for effect in SoundEffect::ALL {
+            assert_close(muted.volume(effect), 0.);
+        }
+    }
+
+    fn sound_app(played: &Arc<Mutex<VecDeque<PlayedSound>>>) -> App {
+        let mut app = App::new();
+        app.insert_resource(SETTINGS)
+            .insert_resource(SoundOutput(Box::new(NullSink {
+                played: played.clone(),
+            })))
+            .add_event::<GameEvent>()
+            .add_system(play_sound_effects);
+        app
+    }
+
+    fn spawn_player(app: &mut App, id: &str, position: Vec2) -> Entity {
+        app.world
+            .spawn()
+            .insert(UserId(id.to_string()))
+            .insert(Transform::from_translation(position.extend(0.)))
+            .id()
+    }
+
+    fn take_played(played: &Arc<Mutex<VecDeque<PlayedSound>>>) -> Vec<PlayedSound> {
+        played
+            .lock()
+            .expect("Null sink lock shouldn't be poisoned")
+            .drain(..)
+            .collect()
+    }
+
+    #[test]
+    fn game_events_play_quieter_further_from_the_local_player() {
+        let played = Arc::default();
+        let mut app = sound_app(&played);
+        let local_player = spawn_player(&mut app, "me", Vec2::new(100., 100.));
+        app.world.entity_mut(local_player).insert(CurrentPlayer);
+        spawn_player(&mut app, "leaving", Vec2::new(100., 550.));
+
+        app.world.send_event(GameEvent::BulletFired {
+            bullet_id: 1,
+            position: Vec2::new(150., 100.),
+            owner: None,
+            owner_reported: false,
+        });
+        app.world.send_event(GameEvent::BulletExpired {
+            bullet_id: 1,
+            position: Vec2::new(800., 100.),
+        });
+        // out of earshot
+        app.world.send_event(GameEvent::BulletFired {
+            bullet_id: 2,
+            position: Vec2::new(2000., 100.),
+            owner: None,
+            owner_reported: false,
+        });
+        // players already there when joining make no sound
+        app.world.send_event(GameEvent::PlayerJoined {
+            user_id: UserId("early".to_string()),
+            position: Vec2::new(100., 100.),
+            aim_angle: 0.,
+            initial: true,
+        });
+        app.world.send_event(GameEvent::PlayerJoined {
+            user_id: UserId("late".to_string()),
+            position: Vec2::new(100., 100.),
+            aim_angle: 0.,
+            initial: false,
+        });
+        app.world.send_event(GameEvent::PlayerLeft {
+            user_id: UserId("leaving".to_string()),
+        });
+        app.update();
+
+        let played = take_played(&played);
+        let effects: Vec<SoundEffect> = played.iter().map(|sound| sound.effect).collect();
+        assert_eq!(
+            effects,
+            vec![
+                SoundEffect::Fire,
+                SoundEffect::Impact,
+                SoundEffect::Join,
+                SoundEffect::Leave
+            ]
+        );
+        assert_close(played[0].volume, SETTINGS.volume(SoundEffect::Fire));
+        assert_close(played[1].volume, SETTINGS.volume(SoundEffect::Impact) * 0.5);
+        assert_close(played[2].volume, SETTINGS.volume(SoundEffect::Join));
+        assert_close(played[3].volume, SETTINGS.volume(SoundEffect::Leave) * 0.75);
+    }
+
+    #[test]
+    fn walking_far_enough_plays_a_footstep() {
+        let played = Arc::default();
+        let mut app = sound_app(&played);
+        let local_player = spawn_player(&mut app, "me", Vec2::ZERO);
+        app.world.entity_mut(local_player).insert(CurrentPlayer);
+        let walker = spawn_player(&mut app, "walker", Vec2::new(700., 0.));
+        app.update();
+
+        for x in [740., 780.] {
+            app.world
+                .get_mut::<Transform>(walker)
+                .expect("The walker should exist")
+                .translation
+                .x = x;
+            app.update();
+        }
+        // a teleport isn't a step
+        app.world
+            .get_mut::<Transform>(walker)
+            .expect("The walker should exist")
+            .translation
+            .x = 1100.;
+        app.update();
+
+        let played = take_played(&played);
+        assert_eq!(played.len(), 1);
+        assert_eq!(played[0].effect, SoundEffect::Footstep);
+        assert_close(
+            played[0].volume,
+            SETTINGS.volume(SoundEffect::Footstep) * attenuation(780.),
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+
+use crate::{
+    components::{CurrentPlayer, InGameEntity, UserId},
+    loading::GameAssets,
+    names::PlayerNames,
+};
+
+/// Who the camera follows while the local player isn't in the game, either
+/// because they died or haven't spawned, or because of `--spectate`.
+#[derive(Default)]
+pub struct Spectator {
+    /// Set by `--spectate`. The local player is never followed, even if the
+    /// server spawns one for this connection.
+    pub forced: bool,
+    pub target: Option<UserId>,
+    /// Show the whole map instead of following anybody
+    pub overview: bool,
+}
+
+impl Spectator {
+    pub fn is_spectating(&self, has_local_player: bool) -> bool {
+        self.forced || !has_local_player
+    }
+}
+
+#[derive(Component)]
+pub struct SpectatorText;
+
+pub fn display_spectator_status(assets: Res<GameAssets>, mut commands: Commands) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(SpectatorText)
+        .insert(InGameEntity);
+}
+
+/// Left and right arrows cycle through the other players, space toggles the
+/// overview of the whole map.
+pub fn cycle_spectator_target(
+    keyboard: Res<Input<KeyCode>>,
+    mut spectator: ResMut<Spectator>,
+    player_names: Res<PlayerNames>,
+    local_player_query: Query<(), With<CurrentPlayer>>,
+    player_query: Query<&UserId, Without<CurrentPlayer>>,
+    mut text_query: Query<&mut Text, With<SpectatorText>>,
+) {
+    let spectating = spectator.is_spectating(!local_player_query.is_empty());
+
+    let mut ids: Vec<&UserId> = player_query.iter().collect();
+    ids.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if spectating {
+        if keyboard.just_pressed(KeyCode::Space) {
+            spectator.overview = !spectator.overview;
+        }
+
+        let current = spectator
+            .target
+            .as_ref()
+            .and_then(|target| ids.iter().position(|id| *id == target));
+        let next = if ids.is_empty() {
+            None
+        } else if keyboard.just_pressed(KeyCode::Right) {
+            spectator.overview = false;
+            Some(current.map_or(0, |index| (index + 1) % ids.len()))
+        } else if keyboard.just_pressed(KeyCode::Left) {
+            spectator.overview = false;
+            Some(current.map_or(ids.len() - 1, |index| (index + ids.len() - 1) % ids.len()))
+        } else {
+            // keep following the same player, or pick someone if they left
+            Some(current.unwrap_or(0))
+        };
+
+        let target = next.map(|index| ids[index].clone());
+        if spectator.target != target {
+            spectator.target = target;
+        }
+    }
+
+    let status = match (&spectator.target, spectating) {
+        (_, false) => String::new(),
+        (Some(target), _) if !spectator.overview => {
+            format!(
+                "Spectating {} | Left/Right: switch | Space: overview",
+                player_names.get(target)
+            )
+        }
+        _ => "Spectating the whole map | Left/Right: follow a player".to_string(),
+    };
+    for mut text in &mut text_query {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.clone();
+        }
+    }
+}
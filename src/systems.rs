@@ -3,62 +3,53 @@ use std::{
     time::Duration,
 };
 
-use bevy::{input::mouse::MouseMotion, prelude::*, render::camera::RenderTarget};
-use clipboard::{ClipboardContext, ClipboardProvider};
-use hathora_client_sdk::{HathoraClient, HathoraTransport};
+use bevy::{prelude::*, render::camera::RenderTarget};
+use hathora_client_sdk::HathoraTransport;
 
 use crate::{
-    components::{BulletId, CurrentPlayer, InterpolationBuffer, MainCamera, UserId},
-    serialization::{AngleInput, ClickInput, MapAsset, MoveInput, UpdateMessage},
-    ProvidedAppId, ProvidedRoomId,
+    atlas::PlayerAnimation,
+    chat::Chat,
+    components::{
+        BulletId, CopyRoomIdButton, CurrentPlayer, InGameEntity, InterpolationBuffer, MainCamera,
+        RoomIdText, UserId,
+    },
+    copy_paste::GameClipboard,
+    diff::{PreviousState, HIT_RADIUS},
+    events::{ChatReceived, GameEvent, LocalPlayerEvent, StatsReported},
+    layers,
+    lifecycle::{start_dying, DeathAnimation, Spawning, SPAWN_START_SCALE},
+    loading::GameAssets,
+    names::PlayerNames,
+    palette::player_color,
+    scoreboard::PlayerStats,
+    serialization::{
+        AngleInput, ChatMessage, ClickInput, MapAsset, MessageHeader, MoveInput, UpdateMessage,
+        Wall, CHAT_MESSAGE_TYPE,
+    },
+    skins::{AssetName, Skinned},
+    spectator::Spectator,
+    AppState,
 };
 
-pub struct RoomId(String);
+pub struct RoomId(pub String);
 
-pub fn log_in_and_set_up_transport(
-    provided_room_id: Res<ProvidedRoomId>,
-    provided_app_id: Res<ProvidedAppId>,
+/// Tears down everything from the room that was just left, so the next one
+/// starts from a clean slate.
+pub fn leave_room(
+    entity_query: Query<Entity, Or<(With<UserId>, With<BulletId>, With<InGameEntity>)>>,
+    mut spectator: ResMut<Spectator>,
+    mut previous_state: ResMut<PreviousState>,
     mut commands: Commands,
 ) {
-    let app_id = provided_app_id
-        .0
-        .clone()
-        .unwrap_or("e2d8571eb89af72f2abbe909def5f19bc4dad0cd475cce5f5b6e9018017d1f1c".to_string());
-
-    let hathora_client = HathoraClient::new(app_id, None);
-
-    let login_result = hathora_client.login_anonymous();
-    let token = login_result.expect("Logging in should succeed");
-
-    let room_id = provided_room_id.0.clone().or_else(|| {
-        debug!("No room provided, creating one");
-        match hathora_client.create(&token, vec![]) {
-            Ok(create_response) => Some(create_response),
-            Err(e) => {
-                error!("Failed to create a room. Error was {}", e);
-                None
-            }
-        }
-    });
-    let room_id = room_id.expect("Room ID exists");
-    commands.insert_resource(RoomId(room_id.to_owned()));
-
-    let user_id = HathoraClient::get_user_from_token(&token).expect("Decoding JWT should succeed");
-    commands.insert_resource(UserId(user_id));
-    let transport = hathora_client
-        .connect(
-            &token,
-            &room_id,
-            hathora_client_sdk::HathoraTransportType::WebSocket,
-        )
-        .expect("Creating web socket should work.");
-    commands.insert_resource(transport);
-}
+    for entity in &entity_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Box<dyn HathoraTransport>>();
+    commands.remove_resource::<RoomId>();
 
-pub fn setup_camera(mut commands: Commands) {
-    commands
-        .spawn_bundle(Camera2dBundle::default())
-        .insert(MainCamera);
+    spectator.target = None;
+    spectator.overview = false;
+    *previous_state = PreviousState::default();
 }
 
 pub struct ButtonTimer(Timer);
@@ -67,11 +58,7 @@ const NORMAL_BUTTON: Color = Color::rgb(0.80, 0.80, 0.80);
 const HOVERED_BUTTON: Color = Color::rgb(0.90, 0.90, 0.90);
 const PRESSED_BUTTON: Color = Color::WHITE;
 
-pub fn display_room_id(
-    asset_server: Res<AssetServer>,
-    mut commands: Commands,
-    room_id: Res<RoomId>,
-) {
+pub fn display_room_id(assets: Res<GameAssets>, mut commands: Commands, room_id: Res<RoomId>) {
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
@@ -82,6 +69,7 @@ pub fn display_room_id(
             color: Color::NONE.into(),
             ..default()
         })
+        .insert(InGameEntity)
         .with_children(|parent| {
             // left vertical fill (border)
             parent
@@ -106,32 +94,37 @@ pub fn display_room_id(
                         })
                         .with_children(|parent| {
                             // text
-                            parent.spawn_bundle(
-                                TextBundle::from_section(
-                                    format!("Room ID: {}", room_id.0),
-                                    TextStyle {
-                                        font: asset_server.load("fonts/FiraMono-Medium.ttf"),
-                                        font_size: 30.0,
-                                        color: Color::WHITE,
-                                    },
+                            parent
+                                .spawn_bundle(
+                                    TextBundle::from_section(
+                                        format!("Room ID: {}", room_id.0),
+                                        TextStyle {
+                                            font: assets.font.clone(),
+                                            font_size: 30.0,
+                                            color: Color::WHITE,
+                                        },
+                                    )
+                                    .with_style(Style {
+                                        margin: UiRect::all(Val::Px(5.0)),
+                                        align_self: AlignSelf::Center,
+                                        ..default()
+                                    }),
                                 )
-                                .with_style(Style {
-                                    margin: UiRect::all(Val::Px(5.0)),
-                                    align_self: AlignSelf::Center,
-                                    ..default()
-                                }),
-                            );
-
-                            parent.spawn_bundle(ButtonBundle {
-                                style: Style {
-                                    size: Size::new(Val::Px(50.0), Val::Px(50.0)),
-                                    margin: UiRect::all(Val::Auto),
+                                .insert(RoomIdText);
+
+                            parent
+                                .spawn_bundle(ButtonBundle {
+                                    style: Style {
+                                        size: Size::new(Val::Px(50.0), Val::Px(50.0)),
+                                        margin: UiRect::all(Val::Auto),
+                                        ..default()
+                                    },
+                                    image: assets.copy_icon.clone().into(),
+                                    color: NORMAL_BUTTON.into(),
                                     ..default()
-                                },
-                                image: asset_server.load("icons/content-copy.png").into(),
-                                color: NORMAL_BUTTON.into(),
-                                ..default()
-                            });
+                                })
+                                .insert(CopyRoomIdButton)
+                                .insert(Skinned(AssetName::CopyIcon));
                         });
                 });
         });
@@ -139,60 +132,94 @@ pub fn display_room_id(
     commands.insert_resource(ButtonTimer(Timer::new(Duration::from_secs(1), false)))
 }
 
-pub struct LoadedMap(Handle<MapAsset>, bool);
-
-pub fn load_map(asset_server: Res<AssetServer>, mut commands: Commands) {
-    let map_loading = asset_server.load("data/map.json");
-    commands.insert_resource(LoadedMap(map_loading, false));
-}
-
+/// Runs once everything has loaded.
 pub fn draw_map(
-    asset_server: Res<AssetServer>,
-    mut loaded_map: ResMut<LoadedMap>,
+    assets: Res<GameAssets>,
     mut commands: Commands,
-    map_assets: ResMut<Assets<MapAsset>>,
+    map_assets: Res<Assets<MapAsset>>,
 ) {
-    let map_asset = map_assets.get(&loaded_map.0);
+    let map = match map_assets.get(&assets.map) {
+        Some(map) => map,
+        None => return,
+    };
+    debug!("Custom asset loaded: {:?}", map);
 
-    if map_asset.is_none() || loaded_map.1 {
-        return;
+    if let Some(floor) = &map.floor {
+        spawn_tiles(
+            &mut commands,
+            assets.map_texture(&floor.texture),
+            map,
+            &map.floor_tiles(),
+            floor.z.unwrap_or(layers::FLOOR),
+            None,
+        );
     }
 
-    let map = map_asset.expect("Verified that map isn't None");
+    for decoration in &map.decorations {
+        spawn_tiles(
+            &mut commands,
+            assets.map_texture(&decoration.texture),
+            map,
+            &decoration.tiles,
+            layers::decoration_z(decoration),
+            None,
+        );
+    }
 
-    debug!("Custom asset loaded: {:?}", map);
-    loaded_map.1 = true;
+    spawn_tiles(
+        &mut commands,
+        assets.wall.clone(),
+        map,
+        &map.walls,
+        layers::WALL,
+        Some(AssetName::Wall),
+    );
+}
 
-    for wall in &map.walls {
-        for x in 0..wall.width {
-            for y in 0..wall.height {
+fn spawn_tiles(
+    commands: &mut Commands,
+    texture: Handle<Image>,
+    map: &MapAsset,
+    rects: &[Wall],
+    z: f32,
+    // set for tiles drawn with an image from the skin rather than the map
+    skinned: Option<AssetName>,
+) {
+    for rect in rects {
+        for x in 0..rect.width {
+            for y in 0..rect.height {
                 let dx = 0.5 + x as f32;
                 let dy = 0.5 + y as f32;
 
-                commands.spawn().insert_bundle({
+                let mut tile = commands.spawn();
+                tile.insert_bundle({
                     SpriteBundle {
-                        texture: asset_server.load("sprites/wall.png"),
+                        texture: texture.clone(),
                         transform: Transform {
                             translation: Vec3::new(
-                                map.tileSize as f32 * (wall.x as f32 + dx),
-                                -map.tileSize as f32 * (wall.y as f32 + dy),
-                                0.,
+                                map.tileSize as f32 * (rect.x as f32 + dx),
+                                -map.tileSize as f32 * (rect.y as f32 + dy),
+                                z,
                             ),
                             ..default()
                         },
                         ..default()
                     }
                 });
+                if let Some(name) = skinned {
+                    tile.insert(Skinned(name));
+                }
             }
         }
     }
 }
 
 pub fn copy_room_id_button(
-    mut interaction_query: Query<(&Interaction, &mut UiColor)>,
-    mut text_query: Query<&mut Text>,
+    mut interaction_query: Query<(&Interaction, &mut UiColor), With<CopyRoomIdButton>>,
+    mut text_query: Query<&mut Text, With<RoomIdText>>,
     mut mouse_button_input: ResMut<Input<MouseButton>>,
     room_id: Res<RoomId>,
+    clipboard: Res<GameClipboard>,
 
     mut button_timer: ResMut<ButtonTimer>,
     time: Res<Time>,
@@ -202,11 +229,18 @@ pub fn copy_room_id_button(
             Interaction::Clicked => {
                 debug!("Button clicked");
                 mouse_button_input.clear_just_pressed(MouseButton::Left);
-                let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-                ctx.set_contents(room_id.0.to_owned()).unwrap();
                 *color = PRESSED_BUTTON.into();
 
-                text_query.single_mut().sections[0].value = "Copied to clipboard".to_string();
+                let message = match clipboard.0.set(room_id.0.to_owned()) {
+                    Ok(()) => "Copied to clipboard",
+                    Err(e) => {
+                        warn!("Failed to copy the room ID. Error was {}", e);
+                        // so it can still be copied by hand from the terminal
+                        println!("Room ID: {}", room_id.0);
+                        "Copy failed, ID printed to console"
+                    }
+                };
+                text_query.single_mut().sections[0].value = message.to_string();
                 button_timer.0.reset();
             }
             Interaction::Hovered => {
@@ -229,122 +263,88 @@ pub fn copy_room_id_button(
 
 pub fn read_from_server(
     mut connection: ResMut<Box<dyn HathoraTransport>>,
-    client_user_id: Res<UserId>,
+    mut previous_state: ResMut<PreviousState>,
     mut player_query: Query<
-        (Entity, &UserId, &mut InterpolationBuffer),
+        (&UserId, &mut InterpolationBuffer),
         (Without<Camera>, Without<BulletId>),
     >,
-    mut bullet_query: Query<
-        (Entity, &BulletId, &mut Transform),
-        (Without<Camera>, Without<UserId>),
-    >,
-
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    mut bullet_query: Query<(&BulletId, &mut Transform), (Without<Camera>, Without<UserId>)>,
+    mut game_events: EventWriter<GameEvent>,
+    mut stats_events: EventWriter<StatsReported>,
+    mut chat_events: EventWriter<ChatReceived>,
+    mut player_names: ResMut<PlayerNames>,
 ) {
     match connection.read_message() {
         Ok(data) => {
             debug!("got some data!");
-            if !data.is_empty() {
-                let update: UpdateMessage =
-                    serde_json::from_slice(&data).expect("Deserialize should work");
+            let header: Option<MessageHeader> = serde_json::from_slice(&data).ok();
+            if header.is_some_and(|header| header.serialized_type == CHAT_MESSAGE_TYPE) {
+                match serde_json::from_slice::<ChatMessage>(&data) {
+                    Ok(chat) => chat_events.send(ChatReceived {
+                        sender: UserId(chat.sender),
+                        message: chat.message,
+                    }),
+                    Err(e) => warn!("Couldn't read a chat message. Error was {}", e),
+                }
+            } else if !data.is_empty() {
+                let update: UpdateMessage = match serde_json::from_slice(&data) {
+                    Ok(update) => update,
+                    Err(e) => {
+                        warn!("Skipping a message that isn't an update. Error was {}", e);
+                        return;
+                    }
+                };
 
-                let mut spawned_players: HashSet<String> = HashSet::new();
+                // players and bullets come and go through these, see apply_game_events
+                game_events.send_batch(previous_state.diff(&update.state).into_iter());
 
-                for (entity, user_id, mut interpolation_buffer) in &mut player_query {
-                    let mut found = false;
-                    spawned_players.insert(user_id.0.clone());
+                for (user_id, mut interpolation_buffer) in &mut player_query {
                     for player_update in update.state.players.iter() {
                         if player_update.id == user_id.0 {
                             debug!("Updating {:?}", &player_update);
-                            found = true;
-
                             interpolation_buffer.0.push_back(Transform {
                                 translation: Vec3::new(
                                     player_update.position.x,
                                     -player_update.position.y,
-                                    0.,
+                                    layers::PLAYER,
                                 ),
                                 rotation: Quat::from_rotation_z(-player_update.aimAngle),
                                 ..default()
                             });
                         }
                     }
-
-                    if !found {
-                        debug!("Despawning {:?}", user_id);
-                        commands.entity(entity).despawn();
-                    }
                 }
 
                 for player_update in update.state.players.iter() {
-                    if !spawned_players.contains(&player_update.id) {
-                        debug!("Spawning {}", &player_update.id);
-                        let mut entity = commands.spawn();
-                        entity
-                            .insert(UserId(player_update.id.clone()))
-                            .insert_bundle(SpriteBundle {
-                                texture: asset_server.load("sprites/player.png"),
-                                transform: Transform {
-                                    translation: Vec3::new(
-                                        player_update.position.x,
-                                        -player_update.position.y,
-                                        0.,
-                                    ),
-                                    rotation: Quat::from_rotation_z(-player_update.aimAngle),
-                                    ..default()
-                                },
-                                ..default()
-                            })
-                            .insert(InterpolationBuffer(VecDeque::new()));
-
-                        if player_update.id == client_user_id.0 {
-                            entity.insert(CurrentPlayer);
+                    if let Some(name) = &player_update.name {
+                        let user_id = UserId(player_update.id.clone());
+                        if !player_names.contains(&user_id, name) {
+                            player_names.insert(user_id, name.clone());
                         }
                     }
-                }
 
-                let mut spawned_bullets: HashSet<i32> = HashSet::new();
-
-                for (bullet_entity, bullet, mut bullet_transform) in &mut bullet_query {
-                    let mut found = false;
-                    spawned_bullets.insert(bullet.0);
+                    if let (Some(kills), Some(deaths)) = (player_update.kills, player_update.deaths)
+                    {
+                        stats_events.send(StatsReported {
+                            user_id: UserId(player_update.id.clone()),
+                            stats: PlayerStats {
+                                kills,
+                                deaths,
+                                score: player_update.score.unwrap_or(kills as i32 - deaths as i32),
+                            },
+                        });
+                    }
+                }
 
+                for (bullet, mut bullet_transform) in &mut bullet_query {
                     for bullet_update in update.state.bullets.iter() {
                         if bullet_update.id == bullet.0 {
                             debug!("Updating {}", bullet.0);
-                            found = true;
                             bullet_transform.translation.x = bullet_update.position.x;
                             bullet_transform.translation.y = -bullet_update.position.y;
                             debug!("Bullet transform is {}", bullet_transform.translation);
                         }
                     }
-
-                    if !found {
-                        debug!("Despawning bullet {}", bullet.0);
-                        commands.entity(bullet_entity).despawn();
-                    }
-                }
-
-                for bullet_update in update.state.bullets.iter() {
-                    if !spawned_bullets.contains(&bullet_update.id) {
-                        debug!("Spawning bullet {}", bullet_update.id);
-                        commands
-                            .spawn()
-                            .insert(BulletId(bullet_update.id))
-                            .insert_bundle(SpriteBundle {
-                                texture: asset_server.load("sprites/bullet.png"),
-                                transform: Transform {
-                                    translation: Vec3::new(
-                                        bullet_update.position.x,
-                                        -bullet_update.position.y,
-                                        0.,
-                                    ),
-                                    ..default()
-                                },
-                                ..default()
-                            });
-                    }
                 }
             }
         }
@@ -354,6 +354,138 @@ pub fn read_from_server(
     }
 }
 
+/// Spawns players and bullets as they come and starts animating them out as
+/// they go.
+pub fn apply_game_events(
+    mut game_events: EventReader<GameEvent>,
+    client_user_id: Res<UserId>,
+    player_query: Query<(Entity, &UserId)>,
+    bullet_query: Query<(Entity, &BulletId)>,
+    assets: Res<GameAssets>,
+    mut commands: Commands,
+) {
+    let events: Vec<&GameEvent> = game_events.iter().collect();
+    let eliminated: HashSet<&UserId> = events
+        .iter()
+        .filter_map(|event| match event {
+            GameEvent::PlayerEliminated { victim, .. } => Some(victim),
+            _ => None,
+        })
+        .collect();
+
+    for event in events {
+        match event {
+            GameEvent::PlayerJoined {
+                user_id,
+                position,
+                aim_angle,
+                ..
+            } => {
+                debug!("Spawning {:?}", user_id);
+                // invisible until the spawn animation starts
+                let mut color = player_color(user_id);
+                color.set_a(0.);
+                let mut entity = commands.spawn();
+                entity
+                    .insert_bundle(SpriteSheetBundle {
+                        // filled in from the sheet once it's loaded
+                        texture_atlas: default(),
+                        sprite: TextureAtlasSprite { color, ..default() },
+                        transform: Transform {
+                            translation: position.extend(layers::PLAYER),
+                            rotation: Quat::from_rotation_z(-aim_angle),
+                            scale: Vec3::splat(SPAWN_START_SCALE),
+                        },
+                        ..default()
+                    })
+                    .insert(user_id.clone())
+                    .insert(InterpolationBuffer(VecDeque::new()))
+                    .insert(PlayerAnimation::new(assets.player.clone()))
+                    .insert(Spawning::default());
+
+                if *user_id == *client_user_id {
+                    entity.insert(CurrentPlayer);
+                }
+            }
+            GameEvent::PlayerLeft { user_id, .. } => {
+                debug!("Despawning {:?}", user_id);
+                let animation = if eliminated.contains(user_id) {
+                    DeathAnimation::Explode
+                } else {
+                    DeathAnimation::Fade
+                };
+                for (entity, _) in player_query.iter().filter(|(_, id)| *id == user_id) {
+                    start_dying(&mut commands.entity(entity), animation);
+                }
+            }
+            GameEvent::BulletFired {
+                bullet_id,
+                position,
+                owner,
+                owner_reported,
+            } => {
+                debug!("Spawning bullet {}", bullet_id);
+                commands
+                    .spawn()
+                    .insert(BulletId(*bullet_id))
+                    .insert(Skinned(AssetName::Bullet))
+                    .insert_bundle(SpriteBundle {
+                        texture: assets.bullet.clone(),
+                        // only tinted when the owner is known for sure
+                        sprite: Sprite {
+                            color: owner
+                                .as_ref()
+                                .filter(|_| *owner_reported)
+                                .map_or(Color::WHITE, player_color),
+                            ..default()
+                        },
+                        transform: Transform::from_translation(position.extend(layers::BULLET)),
+                        ..default()
+                    });
+            }
+            GameEvent::BulletExpired { bullet_id, .. } => {
+                debug!("Despawning bullet {}", bullet_id);
+                for (entity, _) in bullet_query.iter().filter(|(_, id)| id.0 == *bullet_id) {
+                    start_dying(&mut commands.entity(entity), DeathAnimation::QuickFade);
+                }
+            }
+            GameEvent::PlayerEliminated { .. } => {}
+        }
+    }
+}
+
+/// Turns game events involving the local player into feedback events.
+pub fn detect_local_player_events(
+    mut game_events: EventReader<GameEvent>,
+    client_user_id: Res<UserId>,
+    local_player_query: Query<&InterpolationBuffer, With<CurrentPlayer>>,
+    mut local_player_events: EventWriter<LocalPlayerEvent>,
+) {
+    // where the server last put the local player, ahead of the interpolation
+    let local_player_position = local_player_query
+        .get_single()
+        .ok()
+        .and_then(|buffer| buffer.0.back())
+        .map(|transform| transform.translation.truncate());
+
+    for event in game_events.iter() {
+        match event {
+            // the state doesn't say who got hit, so assume a bullet that
+            // vanishes right next to the local player hit them
+            GameEvent::BulletExpired { position, .. }
+                if local_player_position
+                    .is_some_and(|local| local.distance(*position) < HIT_RADIUS) =>
+            {
+                local_player_events.send(LocalPlayerEvent::Hit);
+            }
+            GameEvent::PlayerLeft { user_id, .. } if *user_id == *client_user_id => {
+                local_player_events.send(LocalPlayerEvent::Removed);
+            }
+            _ => {}
+        }
+    }
+}
+
 // determines how quickly our interpolation converges
 const LAMBDA: f32 = 1.;
 
@@ -365,7 +497,7 @@ pub fn update_position_from_interpolation_buffer(
     let delta = (LAMBDA * time.delta_seconds()).max(1.0);
 
     for (mut buffer, mut player_transform) in &mut buffer_query {
-        if let Some(updated_position) = buffer.0.get(0) {
+        if let Some(updated_position) = buffer.0.front() {
             debug!("Updating position by {}", delta);
             player_transform.translation = player_transform
                 .translation
@@ -387,19 +519,51 @@ pub fn update_position_from_interpolation_buffer(
     }
 }
 
+// smallest change in aim, in radians, worth telling the server about
+const ANGLE_EPSILON: f32 = 0.005;
+
 pub fn write_inputs(
     input: Res<Input<KeyCode>>,
     query: Query<(&CurrentPlayer, &Transform)>,
     windows: Res<Windows>,
     camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-    mouse_motion_events: EventReader<MouseMotion>,
     mouse_button_input: Res<Input<MouseButton>>,
+    mut last_angle: Local<Option<f32>>,
+    mut local_player_events: EventWriter<LocalPlayerEvent>,
+    spectator: Res<Spectator>,
+    chat: Res<Chat>,
+    mut was_typing: Local<bool>,
+    mut state: ResMut<State<AppState>>,
 
     mut transport: ResMut<Box<dyn HathoraTransport>>,
 ) {
+    // spectators never send inputs
+    if spectator.forced {
+        return;
+    }
+
+    let mut write_failed = false;
+    let movement_keys = [KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D];
+
+    // movement keys type into the chat while it's open, so stop moving when
+    // it opens and ignore them until it closes
+    let stop_moving = chat.typing && !*was_typing && input.any_pressed(movement_keys);
+    *was_typing = chat.typing;
+    if stop_moving {
+        let input = MoveInput {
+            serialized_type: 0,
+            direction: 0,
+        };
+        let message = serde_json::to_vec(&input).expect("Serialization should work");
+        if let Err(e) = transport.write_message(message) {
+            warn!("Transport failed to write, error was {}", e);
+            write_failed = true;
+        }
+    }
+
     debug!("Processing keyboard input");
-    if input.any_just_released([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D])
-        || input.any_just_pressed([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D])
+    if !chat.typing
+        && (input.any_just_released(movement_keys) || input.any_just_pressed(movement_keys))
     {
         let mut direction = 0;
 
@@ -431,6 +595,7 @@ pub fn write_inputs(
         let message = serde_json::to_vec(&input).expect("Serialization should work");
         if let Err(e) = transport.write_message(message) {
             warn!("Transport failed to write, error was {}", e);
+            write_failed = true;
         }
     }
 
@@ -440,85 +605,164 @@ pub fn write_inputs(
         let message = serde_json::to_vec(&mouse_input).expect("Serialization should work");
         if let Err(e) = transport.write_message(message) {
             warn!("Transport failed to write, error was {}", e);
+            write_failed = true;
+        } else {
+            local_player_events.send(LocalPlayerEvent::Fired);
         }
     }
 
-    if !mouse_motion_events.is_empty() {
-        debug!("Processing mouse input");
-        let (camera, camera_transform) = camera_query.single();
-        let window = if let RenderTarget::Window(id) = camera.target {
-            windows.get(id).unwrap()
-        } else {
-            windows.get_primary().unwrap()
-        };
-        if let Some(cursor_screen_position) = window.cursor_position() {
-            let window_size = Vec2::new(window.width() as f32, window.height() as f32);
-            // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
-            let ndc = (cursor_screen_position / window_size) * 2.0 - Vec2::ONE;
-            // matrix for undoing the projection and camera transform
-            let ndc_to_world =
-                camera_transform.compute_matrix() * camera.projection_matrix().inverse();
-            // use it to convert ndc to world-space coordinates
-            let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
-
-            // reduce it to a 2D value
-            let cursor_world_position: Vec2 = world_pos.truncate();
-
-            for (_, player_transform) in query.iter() {
-                let angle = (cursor_world_position - player_transform.translation.truncate())
-                    .angle_between(Vec2::X);
-                debug!("Angle {}", angle);
-
-                let mouse_input = AngleInput {
-                    serialized_type: 1,
-                    angle,
-                };
-
-                let message = serde_json::to_vec(&mouse_input).expect("Serialization should work");
-                if let Err(e) = transport.write_message(message) {
-                    warn!("Transport failed to write, error was {}", e);
-                }
+    // The cursor's world position also changes when the camera moves or zooms,
+    // so the angle is recomputed every frame and sent whenever it changes.
+    let (camera, camera_transform) = camera_query.single();
+    if let Some(cursor_world_position) = cursor_world_position(&windows, camera, camera_transform) {
+        for (_, player_transform) in query.iter() {
+            let angle = (cursor_world_position - player_transform.translation.truncate())
+                .angle_between(Vec2::X);
+            if last_angle.is_some_and(|last| (last - angle).abs() < ANGLE_EPSILON) {
+                continue;
+            }
+            debug!("Angle {}", angle);
+            *last_angle = Some(angle);
+
+            let mouse_input = AngleInput {
+                serialized_type: 1,
+                angle,
+            };
+
+            let message = serde_json::to_vec(&mouse_input).expect("Serialization should work");
+            if let Err(e) = transport.write_message(message) {
+                warn!("Transport failed to write, error was {}", e);
+                write_failed = true;
             }
         }
     }
+
+    // a socket that can't be written to won't recover, so leave the room
+    if write_failed {
+        if let Err(e) = state.set(AppState::Disconnected) {
+            warn!(
+                "Couldn't switch to {:?}. Error was {:?}",
+                AppState::Disconnected,
+                e
+            );
+        }
+    }
 }
 
-pub fn update_camera(
-    current_player_query: Query<&Transform, (With<CurrentPlayer>, Without<Camera>)>,
-    mut camera_query: Query<(&Camera, &mut Transform), (With<Camera>, Without<CurrentPlayer>)>,
+/// Projects the cursor into world space through the given camera. Returns
+/// `None` while the cursor is outside the camera's window.
+pub fn cursor_world_position(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec2> {
+    let window = if let RenderTarget::Window(id) = camera.target {
+        windows.get(id)?
+    } else {
+        windows.get_primary()?
+    };
+    let cursor_screen_position = window.cursor_position()?;
+
+    let window_size = Vec2::new(window.width(), window.height());
+    // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
+    let ndc = (cursor_screen_position / window_size) * 2.0 - Vec2::ONE;
+    // matrix for undoing the projection and camera transform
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    // use it to convert ndc to world-space coordinates
+    let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
+
+    // reduce it to a 2D value
+    Some(world_pos.truncate())
+}
 
-    map_assets: ResMut<Assets<MapAsset>>,
-    loaded_map: ResMut<LoadedMap>,
-) {
-    let (camera, mut camera_transform) = camera_query.single_mut();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        copy_paste::FallbackClipboard,
+        fakes::{FailingClipboard, FakeTransport},
+    };
+
+    #[test]
+    fn a_failed_copy_points_at_the_console() {
+        let mut app = App::new();
+        app.insert_resource(RoomId("room-1".to_string()))
+            .insert_resource(GameClipboard(Box::new(FallbackClipboard::new(
+                FailingClipboard,
+            ))))
+            .insert_resource(ButtonTimer(Timer::new(Duration::from_secs(1), false)))
+            .insert_resource(Time::default())
+            .insert_resource(Input::<MouseButton>::default())
+            .add_system(copy_room_id_button);
+        app.world.spawn().insert_bundle((
+            CopyRoomIdButton,
+            Interaction::Clicked,
+            UiColor::default(),
+        ));
+        let text = app
+            .world
+            .spawn()
+            .insert_bundle((Text::from_section("Room ID: room-1", default()), RoomIdText))
+            .id();
+
+        app.update();
+
+        let text = app
+            .world
+            .get::<Text>(text)
+            .expect("The room ID text should still exist");
+        assert_eq!(text.sections[0].value, "Copy failed, ID printed to console");
+        // the ID can still be pasted inside the game
+        let clipboard = app.world.resource::<GameClipboard>();
+        assert_eq!(clipboard.0.get().expect("Pasting should work"), "room-1");
+    }
 
-    // can't use single here; CurrentPlayer might not have spawned yet
-    for player_transform in &current_player_query {
-        camera_transform.translation = player_transform.translation;
+    fn server_app(transport: &FakeTransport) -> App {
+        let mut app = App::new();
+        let connection: Box<dyn HathoraTransport> = Box::new(transport.clone());
+        app.insert_resource(connection)
+            .init_resource::<PreviousState>()
+            .init_resource::<PlayerNames>()
+            .add_event::<GameEvent>()
+            .add_event::<StatsReported>()
+            .add_event::<ChatReceived>()
+            .add_system(read_from_server);
+        app
     }
 
-    if let Some(map) = map_assets.get(&loaded_map.0) {
-        let min_gpu = Vec3::splat(-1.);
-        let to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
-        let camera_min = to_world.project_point3(min_gpu);
-        let max_gpu = Vec3::splat(1.);
-        let camera_max = to_world.project_point3(max_gpu);
+    #[test]
+    fn chat_messages_from_the_server_become_chat_events() {
+        let transport = FakeTransport::default();
+        transport.receive(r#"{"type":3,"sender":"user-2","message":"hi"}"#);
+        let mut app = server_app(&transport);
+
+        app.update();
+
+        let events = app.world.resource::<Events<ChatReceived>>();
+        let mut reader = events.get_reader();
+        let received: Vec<_> = reader.iter(events).collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].sender, UserId("user-2".to_string()));
+        assert_eq!(received[0].message, "hi");
+        assert!(app.world.resource::<Events<GameEvent>>().is_empty());
+    }
 
-        let map_min_x = (map.tileSize * map.left) as f32;
-        if (camera_min.x) < map_min_x {
-            camera_transform.translation.x += map_min_x - camera_min.x;
-        }
-        let map_max_x = (map.tileSize * map.right) as f32;
-        if (camera_max.x) > map_max_x {
-            camera_transform.translation.x -= (camera_max.x) - map_max_x;
-        }
-        let map_min_y = -(map.tileSize * map.bottom) as f32;
-        if (camera_min.y) < map_min_y {
-            camera_transform.translation.y += map_min_y - camera_min.y;
-        }
-        let map_max_y = -(map.tileSize * map.top) as f32;
-        if (camera_max.y) > map_max_y {
-            camera_transform.translation.y += map_max_y - camera_max.y;
+    #[test]
+    fn unknown_messages_are_skipped() {
+        let transport = FakeTransport::default();
+        transport.receive(r#"{"type":99,"something":"new"}"#);
+        transport.receive("not even json");
+        transport.receive(r#"{"type":3,"sender":"user-2","message":"still here"}"#);
+        let mut app = server_app(&transport);
+
+        for _ in 0..3 {
+            app.update();
         }
+
+        let events = app.world.resource::<Events<ChatReceived>>();
+        let mut reader = events.get_reader();
+        let received: Vec<_> = reader.iter(events).collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].message, "still here");
     }
 }